@@ -0,0 +1,157 @@
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Environment variable that takes precedence over every key file, unless a profile
+/// names a different one.
+pub const API_KEY_ENV: &str = "CELOXIS_API_KEY";
+
+const KEY_FILE: &str = "api_key";
+const LEGACY_KEY_FILE: &str = "key.txt";
+
+/// Where the API key in use was found.
+#[derive(Debug, Clone)]
+pub enum KeySource {
+    Environment(String),
+    KeyFile(PathBuf),
+    ConfigDir(PathBuf),
+}
+
+impl fmt::Display for KeySource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeySource::Environment(name) => write!(f, "environment variable {}", name),
+            KeySource::KeyFile(path) => write!(f, "key file {:?}", path),
+            KeySource::ConfigDir(path) => write!(f, "{:?}", path),
+        }
+    }
+}
+
+/// The places an API key is looked up, in order of precedence.
+#[derive(Debug, Clone)]
+pub struct KeyLocation {
+    /// Environment variable holding the key
+    pub env_var: String,
+    /// Key file given with `--api-key-file` or by the profile
+    pub key_file: Option<PathBuf>,
+    /// Where `auth set` saves the key, one file per profile in the config directory
+    pub saved_path: PathBuf,
+}
+
+impl KeyLocation {
+    pub fn for_profile(profile: Option<&str>) -> Result<Self, Box<dyn Error>> {
+        let file_name = match profile {
+            Some(name) => format!("{}.{}", KEY_FILE, name),
+            None => KEY_FILE.to_string(),
+        };
+
+        Ok(Self {
+            env_var: API_KEY_ENV.to_string(),
+            key_file: None,
+            saved_path: config_dir()?.join(file_name),
+        })
+    }
+}
+
+/// The directory holding tw-upload's configuration, e.g. `~/.config/tw-upload`.
+pub fn config_dir() -> Result<PathBuf, Box<dyn Error>> {
+    Ok(dirs::config_dir()
+        .ok_or("Could not determine the configuration directory")?
+        .join("tw-upload"))
+}
+
+fn read_key(path: &Path) -> Result<Option<String>, Box<dyn Error>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let key = fs::read_to_string(path)
+        .map_err(|e| format!("Could not read API key from {:?}: {}", path, e))?;
+    let key = key.trim().to_string();
+    Ok((!key.is_empty()).then_some(key))
+}
+
+/// Looks up the API key in order: the environment variable, the key file given on the
+/// command line or by the profile, then the key saved in the config directory.
+pub fn resolve_api_key(
+    location: &KeyLocation,
+) -> Result<Option<(String, KeySource)>, Box<dyn Error>> {
+    if let Ok(key) = std::env::var(&location.env_var) {
+        if !key.trim().is_empty() {
+            let source = KeySource::Environment(location.env_var.clone());
+            return Ok(Some((key.trim().to_string(), source)));
+        }
+    }
+
+    if let Some(path) = &location.key_file {
+        return match read_key(path)? {
+            Some(key) => Ok(Some((key, KeySource::KeyFile(path.clone())))),
+            None => Err(format!("API key file {:?} is missing or empty", path).into()),
+        };
+    }
+
+    let path = location.saved_path.clone();
+    Ok(read_key(&path)?.map(|key| (key, KeySource::ConfigDir(path))))
+}
+
+/// Returns the key from a `key.txt` left in the working directory by older versions,
+/// with the path of the file.
+pub fn legacy_api_key() -> Option<(String, PathBuf)> {
+    let path = std::env::current_dir().ok()?.join(LEGACY_KEY_FILE);
+    let key = read_key(&path).ok().flatten()?;
+    Some((key, path))
+}
+
+/// Saves the key to the config directory, readable only by the current user.
+pub fn save_api_key(location: &KeyLocation, key: &str) -> Result<PathBuf, Box<dyn Error>> {
+    let path = location.saved_path.clone();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(&path)?;
+
+    // The mode only applies on creation, tighten a key file that already existed
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    }
+
+    file.write_all(key.trim().as_bytes())?;
+    Ok(path)
+}
+
+/// Deletes the saved key, returning whether there was one.
+pub fn clear_api_key(location: &KeyLocation) -> Result<bool, Box<dyn Error>> {
+    if !location.saved_path.exists() {
+        return Ok(false);
+    }
+    fs::remove_file(&location.saved_path)?;
+    Ok(true)
+}
+
+/// Shows only the last four characters of a key.
+pub fn mask_key(key: &str) -> String {
+    let visible: String = key
+        .chars()
+        .rev()
+        .take(4)
+        .collect::<Vec<_>>()
+        .into_iter()
+        .rev()
+        .collect();
+    format!(
+        "{}{}",
+        "*".repeat(key.chars().count().saturating_sub(4)),
+        visible
+    )
+}
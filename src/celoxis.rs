@@ -1,20 +1,93 @@
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, Utc};
 use directories::BaseDirs;
 use inquire::{self, validator::Validation};
-use reqwest::blocking::Client;
+use reqwest::blocking::{Client, RequestBuilder, Response};
 use reqwest::header;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::error::Error;
 use std::fs;
+use std::io::{self, Write};
 use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration as StdDuration;
 
-const BASE_URL: &str = "https://app.celoxis.com/psa/api/v2";
+use crate::config::{self, HttpSettings, Rounding};
+
+use crate::auth;
+use crate::error::CeloxisError;
+
+pub const DEFAULT_BASE_URL: &str = "https://app.celoxis.com/psa/api/v2";
+
+/// Records requested per page from list endpoints.
+const PAGE_SIZE: usize = 100;
+
+/// Connection settings for one Celoxis tenant, usually built from a profile.
+#[derive(Debug, Clone)]
+pub struct ApiConfig {
+    pub base_url: String,
+    pub key_location: auth::KeyLocation,
+    pub cache_path: PathBuf,
+    /// Username and time code from the config file
+    pub username: Option<String>,
+    pub time_code: Option<String>,
+    pub rounding: Rounding,
+    /// Profile whose settings are in use, preferences entered at the prompt are saved there
+    pub profile: Option<String>,
+    /// How long cached projects and tasks are used before they are fetched again
+    pub cache_ttl: Duration,
+    /// Work purely from the cache, nothing is fetched or submitted
+    pub offline: bool,
+    pub http: HttpSettings,
+}
+
+impl ApiConfig {
+    /// The cache lives in tw-upload's state directory, next to the session draft, falling
+    /// back to the working directory.
+    pub fn default_cache_path(file_name: &str) -> PathBuf {
+        match dirs::state_dir().or_else(dirs::data_local_dir) {
+            Some(dir) => dir.join("tw-upload").join(file_name),
+            None => PathBuf::from(file_name),
+        }
+    }
+
+    /// Moves a cache older versions kept next to the Timewarrior data to `path`. Only
+    /// existing files are looked at, no Timewarrior directory is created.
+    pub fn move_legacy_cache(file_name: &str, path: &Path) -> Result<(), Box<dyn Error>> {
+        let Some(home) = dirs::home_dir() else {
+            return Ok(());
+        };
+        if path.exists() {
+            return Ok(());
+        }
+
+        let xdg_dir = BaseDirs::new()
+            .map(|base_dirs| base_dirs.data_dir().join("timewarrior"))
+            .unwrap_or_else(|| home.join(".local/share/timewarrior"));
+        let legacy = [xdg_dir, home.join(".timewarrior")]
+            .into_iter()
+            .map(|dir| dir.join(file_name))
+            .find(|legacy| legacy.exists());
+        if let Some(legacy) = legacy {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            // Copied rather than renamed, the state directory may be on another filesystem
+            fs::copy(&legacy, path)?;
+            fs::remove_file(&legacy)?;
+            status!("Moved the Celoxis cache from {:?} to {:?}", legacy, path);
+        }
+        Ok(())
+    }
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UserPreferences {
     pub username: String,
     pub time_code: String,
+    #[serde(skip)]
+    pub rounding: Rounding,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -43,87 +116,229 @@ pub struct CeloxisTimeEntry {
     pub comments: String,
 }
 
+/// What happened to one submitted time entry.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EntryOutcome {
+    Submitted { id: Option<String> },
+    Failed { reason: String },
+    /// Celoxis accepted the request but its answer doesn't say what became of the entry
+    Unknown,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 struct CeloxisResponse<T> {
     data: Vec<T>,
     total_records: Option<i32>,
 }
 
+/// A tag set and the Celoxis task it was last submitted to.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AssignmentRecord {
+    pub tags: Vec<String>,
+    pub project_id: String,
+    pub task_id: String,
+    pub last_used: DateTime<Utc>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CacheData {
     projects: HashMap<String, CeloxisProject>,
     tasks: HashMap<String, Vec<CeloxisTask>>,
-    last_updated: DateTime<Utc>,
+    /// When the project list was fetched, older versions stored it as `last_updated`
+    #[serde(default, alias = "last_updated")]
+    projects_updated: Option<DateTime<Utc>>,
+    /// When each project's task list was fetched
+    #[serde(default)]
+    tasks_updated: HashMap<String, DateTime<Utc>>,
+    /// Only read to migrate preferences saved by older versions to the config file
+    #[serde(default, skip_serializing)]
     user_prefs: Option<UserPreferences>,
+    #[serde(default)]
+    assignment_history: Vec<AssignmentRecord>,
 }
 
 pub struct CeloxisApi {
     client: Client,
-    cache_path: PathBuf,
+    config: ApiConfig,
     cache: Option<CacheData>,
 }
 
 impl CeloxisApi {
-    fn ensure_api_key_exists() -> Result<(), Box<dyn Error>> {
-        if !Path::new("key.txt").exists() {
-            println!("API key file (key.txt) not found.");
-            println!("Please enter your Celoxis API key:");
-            let api_key = inquire::Text::new("API Key:")
-                .with_validator(|input: &str| {
-                    if input.trim().is_empty() {
-                        Ok(Validation::Invalid("API key cannot be empty".into()))
-                    } else {
-                        Ok(Validation::Valid)
-                    }
-                })
-                .prompt()?;
-            fs::write("key.txt", api_key)?;
-            println!("API key saved to key.txt");
+    /// Returns the API key from the environment or a key file. Without one, a key left
+    /// in `key.txt` by older versions is checked and moved to the config directory if the
+    /// user agrees, or the user is asked for a new one.
+    fn ensure_api_key(config: &ApiConfig) -> Result<String, Box<dyn Error>> {
+        if let Some((api_key, _)) = auth::resolve_api_key(&config.key_location)? {
+            return Ok(api_key);
         }
-        Ok(())
-    }
 
-    pub fn ensure_user_prefs(&mut self) -> Result<UserPreferences, Box<dyn Error>> {
-        if let Some(cache) = &self.cache {
-            if let Some(prefs) = &cache.user_prefs {
-                return Ok(prefs.clone());
+        // Nothing is sent in offline mode, so there is no need to ask for a key
+        if config.offline {
+            return Ok(String::new());
+        }
+
+        // The file is only found when running in the directory it was left in, so ask
+        // before taking it over
+        if let Some((api_key, legacy_path)) = auth::legacy_api_key() {
+            let migrate = inquire::Confirm::new(&format!(
+                "Use the API key found in {:?} and save it to the config directory?",
+                legacy_path
+            ))
+            .with_default(true)
+            .prompt()?;
+            if migrate {
+                match Self::validate_api_key(config, &api_key) {
+                    Ok(()) => {
+                        let path = auth::save_api_key(&config.key_location, &api_key)?;
+                        status!(
+                            "Moved the API key from {:?} to {:?}, it can be deleted now",
+                            legacy_path, path
+                        );
+                        return Ok(api_key);
+                    }
+                    Err(e) => status!("Celoxis didn't accept the key in {:?}: {}", legacy_path, e),
+                }
             }
         }
 
-        println!("User preferences not found. Please enter your information:");
+        status!("Celoxis API key not found.");
+        status!(
+            "Set {} or enter your Celoxis API key to save it:",
+            config.key_location.env_var
+        );
+        Self::prompt_api_key(config)
+    }
 
-        let username = inquire::Text::new("Celoxis Username:")
+    /// Asks for an API key, checks it against Celoxis and saves it to the config directory.
+    pub fn prompt_api_key(config: &ApiConfig) -> Result<String, Box<dyn Error>> {
+        let api_key = inquire::Password::new("API Key:")
+            .without_confirmation()
             .with_validator(|input: &str| {
                 if input.trim().is_empty() {
-                    Ok(Validation::Invalid("Username cannot be empty".into()))
+                    Ok(Validation::Invalid("API key cannot be empty".into()))
                 } else {
                     Ok(Validation::Valid)
                 }
             })
             .prompt()?;
+        let api_key = api_key.trim().to_string();
 
-        let time_code = inquire::Text::new("Default Time Code (e.g., engineering_labor):")
-            .with_validator(|input: &str| {
+        Self::validate_api_key(config, &api_key)?;
+        let path = auth::save_api_key(&config.key_location, &api_key)?;
+        status!("API key saved to {:?}", path);
+
+        Ok(api_key)
+    }
+
+    /// Checks that Celoxis accepts the key by requesting a single project.
+    pub fn validate_api_key(config: &ApiConfig, api_key: &str) -> Result<(), Box<dyn Error>> {
+        let client = Self::build_client(api_key, &config.http)?;
+        let url = format!("{}/projects", config.base_url);
+        let response = send_with_retry(&config.http, Retry::Always, || {
+            client.get(&url).query(&[("limit", "1")])
+        })?;
+
+        CeloxisError::check_status(response)?;
+        Ok(())
+    }
+
+    fn build_client(api_key: &str, http: &HttpSettings) -> Result<Client, Box<dyn Error>> {
+        let mut headers = header::HeaderMap::new();
+        headers.insert(
+            "Authorization",
+            header::HeaderValue::from_str(&format!("bearer {}", api_key.trim()))?,
+        );
+        headers.insert(
+            "Content-Type",
+            header::HeaderValue::from_static("application/json"),
+        );
+
+        Ok(Client::builder()
+            .default_headers(headers)
+            .connect_timeout(StdDuration::from_secs(http.connect_timeout_secs))
+            .timeout(StdDuration::from_secs(http.timeout_secs))
+            .build()?)
+    }
+
+    fn prompt_non_empty(message: &str, error: &'static str) -> Result<String, Box<dyn Error>> {
+        Ok(inquire::Text::new(message)
+            .with_validator(move |input: &str| {
                 if input.trim().is_empty() {
-                    Ok(Validation::Invalid("Time code cannot be empty".into()))
+                    Ok(Validation::Invalid(error.into()))
                 } else {
                     Ok(Validation::Valid)
                 }
             })
-            .prompt()?;
+            .prompt()?)
+    }
 
-        let prefs = UserPreferences {
-            username,
-            time_code,
+    /// Moves the preferences older versions kept in the cache to the config file, without
+    /// overwriting any that are already configured there.
+    fn migrate_user_prefs(&mut self) -> Result<(), Box<dyn Error>> {
+        let Some(legacy) = self.cache.as_mut().and_then(|cache| cache.user_prefs.take()) else {
+            return Ok(());
         };
 
-        // Update cache with new preferences
-        if let Some(cache) = &mut self.cache {
-            cache.user_prefs = Some(prefs.clone());
-            self.save_cache()?;
+        let username = self.config.username.is_none().then_some(legacy.username);
+        let time_code = self.config.time_code.is_none().then_some(legacy.time_code);
+        if username.is_some() || time_code.is_some() {
+            let path = config::save_user_prefs(
+                self.config.profile.as_deref(),
+                username.as_deref(),
+                time_code.as_deref(),
+            )?;
+            status!("Moved user preferences from the cache to {:?}", path);
+            self.config.username = self.config.username.take().or(username);
+            self.config.time_code = self.config.time_code.take().or(time_code);
         }
 
-        Ok(prefs)
+        self.save_cache()
+    }
+
+    /// Returns the username and time code from the config file, asking for missing ones
+    /// and saving them there.
+    pub fn ensure_user_prefs(&mut self) -> Result<UserPreferences, Box<dyn Error>> {
+        let mut username = self.config.username.clone();
+        let mut time_code = self.config.time_code.clone();
+
+        if username.is_none() || time_code.is_none() {
+            status!("User preferences not found. Please enter your information:");
+            let new_username = match username {
+                Some(_) => None,
+                None => Some(Self::prompt_non_empty(
+                    "Celoxis Username:",
+                    "Username cannot be empty",
+                )?),
+            };
+            let new_time_code = match time_code {
+                Some(_) => None,
+                None => Some(Self::prompt_non_empty(
+                    "Default Time Code (e.g., engineering_labor):",
+                    "Time code cannot be empty",
+                )?),
+            };
+
+            let path = config::save_user_prefs(
+                self.config.profile.as_deref(),
+                new_username.as_deref(),
+                new_time_code.as_deref(),
+            )?;
+            status!("Saved user preferences to {:?}", path);
+            username = username.or(new_username);
+            time_code = time_code.or(new_time_code);
+        }
+
+        self.config.username = username.clone();
+        self.config.time_code = time_code.clone();
+        Ok(UserPreferences {
+            username: username.unwrap_or_default(),
+            time_code: time_code.unwrap_or_default(),
+            rounding: self.config.rounding,
+        })
+    }
+
+    pub fn rounding(&self) -> Rounding {
+        self.config.rounding
     }
 
     fn ensure_directories_exist(cache_path: &Path) -> Result<(), Box<dyn Error>> {
@@ -133,62 +348,35 @@ impl CeloxisApi {
         Ok(())
     }
 
-    pub fn new() -> Result<Self, Box<dyn Error>> {
-        Self::ensure_api_key_exists()?;
+    pub fn new(config: ApiConfig) -> Result<Self, Box<dyn Error>> {
+        let api_key = Self::ensure_api_key(&config)?;
+        let client = Self::build_client(&api_key, &config.http)?;
 
-        let api_key = fs::read_to_string("key.txt")?;
-        let mut headers = header::HeaderMap::new();
-        headers.insert(
-            "Authorization",
-            header::HeaderValue::from_str(&format!("bearer {}", api_key.trim()))?,
-        );
-        headers.insert(
-            "Content-Type",
-            header::HeaderValue::from_static("application/json"),
-        );
-
-        let client = Client::builder().default_headers(headers).build()?;
-
-        let cache_path = if let Some(base_dirs) = BaseDirs::new() {
-            if Path::new(&format!("{}/.local/share/timewarrior", env!("HOME"))).exists() {
-                PathBuf::from(format!(
-                    "{}/.local/share/timewarrior/celoxis_cache.json",
-                    env!("HOME")
-                ))
-            } else if Path::new(&format!("{}/.timewarrior", env!("HOME"))).exists() {
-                PathBuf::from(format!("{}/.timewarrior/celoxis_cache.json", env!("HOME")))
-            } else {
-                PathBuf::from(format!(
-                    "{}/.local/share/timewarrior/celoxis_cache.json",
-                    env!("HOME")
-                ))
-            }
-        } else {
-            PathBuf::from("celoxis_cache.json")
-        };
-
-        Self::ensure_directories_exist(&cache_path)?;
+        Self::ensure_directories_exist(&config.cache_path)?;
 
         let mut api = Self {
             client,
-            cache_path,
+            config,
             cache: None,
         };
         api.load_cache()?;
+        api.migrate_user_prefs()?;
 
         Ok(api)
     }
 
     fn load_cache(&mut self) -> Result<(), Box<dyn Error>> {
-        if self.cache_path.exists() {
-            let cache_content = fs::read_to_string(&self.cache_path)?;
+        if self.config.cache_path.exists() {
+            let cache_content = fs::read_to_string(&self.config.cache_path)?;
             self.cache = Some(serde_json::from_str(&cache_content)?);
         } else {
             self.cache = Some(CacheData {
                 projects: HashMap::new(),
                 tasks: HashMap::new(),
-                last_updated: Utc::now(),
+                projects_updated: None,
+                tasks_updated: HashMap::new(),
                 user_prefs: None,
+                assignment_history: Vec::new(),
             });
         }
         Ok(())
@@ -196,77 +384,240 @@ impl CeloxisApi {
 
     fn save_cache(&self) -> Result<(), Box<dyn Error>> {
         if let Some(cache) = &self.cache {
-            if let Some(parent) = self.cache_path.parent() {
+            if let Some(parent) = self.config.cache_path.parent() {
                 fs::create_dir_all(parent)?;
             }
-            fs::write(&self.cache_path, serde_json::to_string_pretty(cache)?)?;
+            fs::write(&self.config.cache_path, serde_json::to_string_pretty(cache)?)?;
         }
         Ok(())
     }
 
+    fn is_fresh(&self, updated: Option<DateTime<Utc>>) -> bool {
+        updated.is_some_and(|updated| Utc::now() - updated < self.config.cache_ttl)
+    }
+
+    /// Returns the active projects, from the cache while it is younger than the TTL.
+    /// In offline mode the cache is used however old it is.
     pub fn get_projects(
         &mut self,
         force_refresh: bool,
     ) -> Result<Vec<CeloxisProject>, Box<dyn Error>> {
-        if !force_refresh {
-            if let Some(cache) = &self.cache {
+        if let Some(cache) = &self.cache {
+            let cached = cache.projects_updated.is_some() && !cache.projects.is_empty();
+            if self.config.offline {
+                if !cached {
+                    return Err("No cached projects, run once without --offline".into());
+                }
+                return Ok(cache.projects.values().cloned().collect());
+            }
+            if !force_refresh && cached && self.is_fresh(cache.projects_updated) {
                 return Ok(cache.projects.values().cloned().collect());
             }
         }
 
-        let params = [("filter", "{state : Active}")];
-        let response: CeloxisResponse<CeloxisProject> = self
-            .client
-            .get(&format!("{}/projects", BASE_URL))
-            .query(&params)
-            .send()?
-            .json()?;
+        let projects: Vec<CeloxisProject> =
+            self.get_all("projects", "{state : Active}", "projects")?;
 
         if let Some(cache) = &mut self.cache {
             cache.projects.clear();
-            for project in &response.data {
+            for project in &projects {
                 cache.projects.insert(project.id.clone(), project.clone());
             }
-            cache.last_updated = Utc::now();
+            cache.projects_updated = Some(Utc::now());
             self.save_cache()?;
         }
 
-        Ok(response.data)
+        Ok(projects)
     }
 
+    /// Returns the tasks of a project, cached per project with the same TTL as projects.
     pub fn get_tasks(
         &mut self,
         project_id: &str,
         force_refresh: bool,
     ) -> Result<Vec<CeloxisTask>, Box<dyn Error>> {
-        if !force_refresh {
-            if let Some(cache) = &self.cache {
-                if let Some(tasks) = cache.tasks.get(project_id) {
-                    return Ok(tasks.clone());
-                }
+        if let Some(cache) = &self.cache {
+            let cached = cache.tasks.get(project_id);
+            let updated = cache.tasks_updated.get(project_id).copied();
+            if self.config.offline {
+                return cached.cloned().ok_or_else(|| {
+                    format!(
+                        "No cached tasks for project '{}', run once without --offline",
+                        project_id
+                    )
+                    .into()
+                });
+            }
+            if let Some(tasks) = cached.filter(|_| !force_refresh && self.is_fresh(updated)) {
+                return Ok(tasks.clone());
             }
         }
 
         let filter_json = format!("{{\"project.id\":\"{}\"}}", project_id);
-        println!("Fetching tasks with filter: {}", filter_json);
+        status!("Fetching tasks with filter: {}", filter_json);
 
-        let params = [("filter", filter_json)];
-        let response: CeloxisResponse<CeloxisTask> = self
-            .client
-            .get(&format!("{}/tasks", BASE_URL))
-            .query(&params)
-            .send()?
-            .json()?;
+        let tasks: Vec<CeloxisTask> = self.get_all("tasks", &filter_json, "tasks")?;
 
         if let Some(cache) = &mut self.cache {
+            cache.tasks.insert(project_id.to_string(), tasks.clone());
             cache
-                .tasks
-                .insert(project_id.to_string(), response.data.clone());
-            cache.last_updated = Utc::now();
+                .tasks_updated
+                .insert(project_id.to_string(), Utc::now());
+            self.save_cache()?;
+        }
+
+        Ok(tasks)
+    }
+
+    /// Fetches every page of a list endpoint. Celoxis returns at most `limit` records per
+    /// request, so pages are requested by `offset` until `total_records` have arrived.
+    fn get_all<T: DeserializeOwned>(
+        &self,
+        endpoint: &str,
+        filter: &str,
+        what: &str,
+    ) -> Result<Vec<T>, Box<dyn Error>> {
+        let url = format!("{}/{}", self.config.base_url, endpoint);
+        let mut records = Vec::new();
+        let mut progress_shown = false;
+
+        loop {
+            let offset = records.len().to_string();
+            let limit = PAGE_SIZE.to_string();
+            let params = [
+                ("filter", filter),
+                ("offset", offset.as_str()),
+                ("limit", limit.as_str()),
+            ];
+            let response = send_with_retry(&self.config.http, Retry::Always, || {
+                self.client.get(&url).query(&params)
+            })?;
+            let response: CeloxisResponse<T> = CeloxisError::check(response)?;
+
+            let received = response.data.len();
+            records.extend(response.data);
+
+            let total = response.total_records.map(|total| total.max(0) as usize);
+            let done = match total {
+                Some(total) => records.len() >= total,
+                None => received < PAGE_SIZE,
+            };
+            // Only show progress once it is clear that there is more than one page
+            if !done || progress_shown {
+                match total {
+                    Some(total) => eprint!("\rFetching {}: {}/{}", what, records.len(), total),
+                    None => eprint!("\rFetching {}: {}", what, records.len()),
+                }
+                io::stderr().flush()?;
+                progress_shown = true;
+            }
+            if done || received == 0 {
+                break;
+            }
+        }
+
+        if progress_shown {
+            eprintln!();
+        }
+
+        Ok(records)
+    }
+
+    pub fn cache_path(&self) -> &Path {
+        &self.config.cache_path
+    }
+
+    pub fn cache_ttl(&self) -> Duration {
+        self.config.cache_ttl
+    }
+
+    pub fn is_offline(&self) -> bool {
+        self.config.offline
+    }
+
+    /// When the project list was last fetched, if it is cached at all.
+    pub fn projects_updated(&self) -> Option<DateTime<Utc>> {
+        self.cache.as_ref()?.projects_updated
+    }
+
+    /// When the tasks of a project were last fetched, if they are cached at all.
+    pub fn tasks_updated(&self, project_id: &str) -> Option<DateTime<Utc>> {
+        let cache = self.cache.as_ref()?;
+        cache.tasks.get(project_id)?;
+        // Task lists cached by older versions have no timestamp of their own
+        cache
+            .tasks_updated
+            .get(project_id)
+            .copied()
+            .or(cache.projects_updated)
+    }
+
+    /// Whether cached data of this age is still used without asking Celoxis.
+    pub fn is_stale(&self, updated: Option<DateTime<Utc>>) -> bool {
+        !self.is_fresh(updated)
+    }
+
+    /// Ids of the projects with a cached task list, sorted.
+    pub fn cached_task_projects(&self) -> Vec<String> {
+        let mut ids: Vec<String> = self
+            .cache
+            .iter()
+            .flat_map(|cache| cache.tasks.keys().cloned())
+            .collect();
+        ids.sort();
+        ids
+    }
+
+    /// Drops all cached projects and tasks but keeps the assignment history.
+    pub fn clear_cache(&mut self) -> Result<(), Box<dyn Error>> {
+        if let Some(cache) = &mut self.cache {
+            cache.projects.clear();
+            cache.tasks.clear();
+            cache.tasks_updated.clear();
+            cache.projects_updated = None;
+            self.save_cache()?;
+        }
+        Ok(())
+    }
+
+    /// Remembers the task a tag set was submitted to, replacing any earlier choice.
+    pub fn record_assignment(
+        &mut self,
+        tags: &[String],
+        project_id: &str,
+        task_id: &str,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut tags = tags.to_vec();
+        tags.sort();
+
+        if let Some(cache) = &mut self.cache {
+            cache.assignment_history.retain(|record| record.tags != tags);
+            cache.assignment_history.push(AssignmentRecord {
+                tags,
+                project_id: project_id.to_string(),
+                task_id: task_id.to_string(),
+                last_used: Utc::now(),
+            });
             self.save_cache()?;
         }
+        Ok(())
+    }
 
-        Ok(response.data)
+    /// The task most recently used for exactly this tag set.
+    pub fn suggest_assignment(&self, tags: &[String]) -> Option<&AssignmentRecord> {
+        let mut tags = tags.to_vec();
+        tags.sort();
+
+        self.cache
+            .as_ref()?
+            .assignment_history
+            .iter()
+            .filter(|record| record.tags == tags)
+            .max_by_key(|record| record.last_used)
+    }
+
+    pub fn get_cached_projects(&self) -> Vec<&CeloxisProject> {
+        self.cache.iter().flat_map(|cache| cache.projects.values()).collect()
     }
 
     pub fn get_cached_project(&self, project_id: &str) -> Option<&CeloxisProject> {
@@ -277,19 +628,592 @@ impl CeloxisApi {
         self.cache.as_ref()?.tasks.get(project_id)
     }
 
+    /// Submits the entries in one request and returns what happened to each of them, in
+    /// the same order. Errors that leave it unknown whether anything was saved, like
+    /// network or authentication failures, are returned as an error instead.
     pub fn submit_time_entries(
         &self,
-        entries: Vec<CeloxisTimeEntry>,
-    ) -> Result<(), Box<dyn Error>> {
-        let url = format!("{}/timeEntries", BASE_URL);
+        entries: &[CeloxisTimeEntry],
+    ) -> Result<Vec<EntryOutcome>, Box<dyn Error>> {
+        if self.config.offline {
+            return Err("Can't submit time entries in offline mode".into());
+        }
+        let url = format!("{}/timeEntries", self.config.base_url);
 
-        let response = self.client.post(&url).json(&entries).send()?;
+        // Creating time entries isn't idempotent, so only retry when Celoxis can't have
+        // received them
+        let result = send_with_retry(&self.config.http, Retry::IfNotSent, || {
+            self.client.post(&url).json(entries)
+        });
+        let response = match result {
+            Err(CeloxisError::Network(e)) if e.is_timeout() => {
+                status!(
+                    "The request timed out after it was sent, the entries may have reached \
+                     Celoxis. Check your timesheet there before submitting them again."
+                );
+                return Err(CeloxisError::Network(e).into());
+            }
+            result => result?,
+        };
 
-        if !response.status().is_success() {
-            let error_json = response.json::<serde_json::Value>()?;
-            return Err(format!("Failed to submit time entries: {:?}", error_json).into());
+        match CeloxisError::check_status(response) {
+            Ok((_, body)) => Ok(Self::parse_outcomes(&body, entries)),
+            Err(CeloxisError::Validation {
+                message,
+                entries: errors,
+            }) => {
+                // A rejected request saves nothing, entries without an error of their own
+                // failed because of the others
+                let mut outcomes = vec![
+                    EntryOutcome::Failed {
+                        reason: format!("not saved, Celoxis rejected the request: {}", message),
+                    };
+                    entries.len()
+                ];
+                for error in errors {
+                    let Some(outcome) = error.index.and_then(|index| outcomes.get_mut(index))
+                    else {
+                        continue;
+                    };
+                    let reason = match &error.field {
+                        Some(field) => format!("{}: {}", field, error.message),
+                        None => error.message.clone(),
+                    };
+                    *outcome = EntryOutcome::Failed { reason };
+                }
+                Ok(outcomes)
+            }
+            Err(e) => Err(e.into()),
         }
+    }
 
-        Ok(())
+    /// Reads the per-entry results of a successful submission. Celoxis answers with the
+    /// created records, possibly wrapped in `data`, and marks entries it could not save
+    /// with an error. A record belongs to the entry its `index` names, to the entry at its
+    /// position when there is one record per entry, or else to the entry with the same
+    /// date, task and hours. Entries no record belongs to are unknown.
+    fn parse_outcomes(body: &str, entries: &[CeloxisTimeEntry]) -> Vec<EntryOutcome> {
+        let mut outcomes = vec![EntryOutcome::Unknown; entries.len()];
+        let Ok(json) = serde_json::from_str::<serde_json::Value>(body) else {
+            return outcomes;
+        };
+        let Some(items) = json
+            .as_array()
+            .or_else(|| json.get("data").and_then(serde_json::Value::as_array))
+        else {
+            return outcomes;
+        };
+
+        let mut matched = vec![false; entries.len()];
+        for (position, item) in items.iter().enumerate() {
+            let index = item
+                .get("index")
+                .and_then(serde_json::Value::as_u64)
+                .map(|index| index as usize)
+                .or(Some(position).filter(|_| items.len() == entries.len()))
+                .or_else(|| {
+                    (0..entries.len())
+                        .find(|&idx| !matched[idx] && Self::is_record_of(item, &entries[idx]))
+                });
+            let Some(index) = index.filter(|index| *index < entries.len()) else {
+                continue;
+            };
+            matched[index] = true;
+            outcomes[index] = Self::parse_outcome(item);
+        }
+        outcomes
+    }
+
+    /// Whether a returned record has the date, task and hours of the entry.
+    fn is_record_of(item: &serde_json::Value, entry: &CeloxisTimeEntry) -> bool {
+        let text = |key: &str| {
+            item.get(key).and_then(|value| match value {
+                serde_json::Value::String(text) => Some(text.clone()),
+                serde_json::Value::Number(number) => Some(number.to_string()),
+                _ => None,
+            })
+        };
+        let hours = item.get("hours").and_then(serde_json::Value::as_f64);
+
+        text("date").is_some_and(|date| date.starts_with(&entry.date))
+            && text("task").is_some_and(|task| task == entry.task)
+            && hours.is_some_and(|hours| (hours - entry.hours).abs() < 0.005)
+    }
+
+    fn parse_outcome(item: &serde_json::Value) -> EntryOutcome {
+        let error = ["error", "errors", "errorMessage"]
+            .iter()
+            .find_map(|key| item.get(*key))
+            .filter(|error| !error.is_null());
+        if let Some(error) = error {
+            let reason = match error {
+                serde_json::Value::String(text) => text.clone(),
+                other => other.to_string(),
+            };
+            return EntryOutcome::Failed { reason };
+        }
+
+        let id = item.get("id").and_then(|id| match id {
+            serde_json::Value::String(id) => Some(id.clone()),
+            serde_json::Value::Number(id) => Some(id.to_string()),
+            _ => None,
+        });
+        EntryOutcome::Submitted { id }
+    }
+}
+
+/// Which failures a request may be sent again after.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Retry {
+    /// Reads can be repeated after any temporary failure
+    Always,
+    /// Writes are only repeated when Celoxis can't have acted on them: the connection
+    /// failed or the request was turned away by rate limiting
+    IfNotSent,
+}
+
+/// Longest wait accepted from a `Retry-After` header.
+const MAX_RETRY_AFTER: u64 = 300;
+
+/// Reads a `Retry-After` header, given either in seconds or as an HTTP date.
+pub fn parse_retry_after(value: &str) -> Option<StdDuration> {
+    let value = value.trim();
+    let seconds = match value.parse::<u64>() {
+        Ok(seconds) => seconds,
+        Err(_) => {
+            let date = DateTime::parse_from_rfc2822(value).ok()?;
+            (date.with_timezone(&Utc) - Utc::now()).num_seconds().max(0) as u64
+        }
+    };
+    Some(StdDuration::from_secs(seconds.min(MAX_RETRY_AFTER)))
+}
+
+/// Sends a request, retrying temporary failures with exponential backoff. A `Retry-After`
+/// header on 429 and 503 responses takes precedence over the backoff delay.
+fn send_with_retry(
+    http: &HttpSettings,
+    retry: Retry,
+    request: impl Fn() -> RequestBuilder,
+) -> Result<Response, CeloxisError> {
+    let mut attempt = 0;
+
+    loop {
+        let factor = 1u64 << attempt.min(16);
+        let backoff = StdDuration::from_millis(http.backoff_ms.saturating_mul(factor));
+        let (reason, delay) = match request().send() {
+            Ok(response) => {
+                let status = response.status();
+                let retry_after = response
+                    .headers()
+                    .get(header::RETRY_AFTER)
+                    .and_then(|value| {
+                        let delay = value.to_str().ok().and_then(parse_retry_after);
+                        if delay.is_none() {
+                            status!("Ignoring unreadable Retry-After header {:?}", value);
+                        }
+                        delay
+                    });
+
+                let retryable = match retry {
+                    Retry::Always => status.as_u16() == 429 || status.is_server_error(),
+                    Retry::IfNotSent => status.as_u16() == 429,
+                };
+                if !retryable || attempt >= http.retries {
+                    return Ok(response);
+                }
+                (status.to_string(), retry_after.unwrap_or(backoff))
+            }
+            Err(e) => {
+                let retryable = match retry {
+                    Retry::Always => e.is_connect() || e.is_timeout(),
+                    Retry::IfNotSent => e.is_connect(),
+                };
+                if !retryable || attempt >= http.retries {
+                    return Err(e.into());
+                }
+                let reason = if e.is_timeout() { "timed out" } else { "connection failed" };
+                (reason.to_string(), backoff)
+            }
+        };
+
+        attempt += 1;
+        status!(
+            "Celoxis request failed ({}), retrying in {:.1}s ({}/{})",
+            reason,
+            delay.as_secs_f64(),
+            attempt,
+            http.retries
+        );
+        thread::sleep(delay);
+    }
+}
+
+/// Describes how long ago something was cached, e.g. "3 hours ago".
+pub fn format_age(updated: DateTime<Utc>) -> String {
+    let age = Utc::now() - updated;
+    let (value, unit) = if age.num_days() > 0 {
+        (age.num_days(), "day")
+    } else if age.num_hours() > 0 {
+        (age.num_hours(), "hour")
+    } else if age.num_minutes() > 0 {
+        (age.num_minutes(), "minute")
+    } else {
+        return "just now".to_string();
+    };
+    format!("{} {}{} ago", value, unit, if value == 1 { "" } else { "s" })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+
+    /// A local HTTP server answering each request with the next of the given responses,
+    /// one connection per request. Requests after the last response are refused.
+    struct StubServer {
+        url: String,
+        requests: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl StubServer {
+        fn start(responses: Vec<String>) -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let url = format!("http://{}", listener.local_addr().unwrap());
+            let requests = Arc::new(Mutex::new(Vec::new()));
+
+            let seen = Arc::clone(&requests);
+            thread::spawn(move || {
+                for response in responses {
+                    let Ok((mut stream, _)) = listener.accept() else {
+                        return;
+                    };
+                    let mut reader = BufReader::new(stream.try_clone().unwrap());
+                    let mut request_line = String::new();
+                    reader.read_line(&mut request_line).unwrap();
+
+                    let mut content_length = 0;
+                    loop {
+                        let mut line = String::new();
+                        reader.read_line(&mut line).unwrap();
+                        if line.trim().is_empty() {
+                            break;
+                        }
+                        if let Some((name, value)) = line.split_once(':') {
+                            if name.eq_ignore_ascii_case("content-length") {
+                                content_length = value.trim().parse().unwrap();
+                            }
+                        }
+                    }
+                    let mut body = vec![0; content_length];
+                    reader.read_exact(&mut body).unwrap();
+
+                    seen.lock().unwrap().push(request_line.trim().to_string());
+                    stream.write_all(response.as_bytes()).unwrap();
+                }
+            });
+
+            Self { url, requests }
+        }
+
+        fn requests(&self) -> Vec<String> {
+            self.requests.lock().unwrap().clone()
+        }
+    }
+
+    fn response(status: &str, headers: &[&str], body: &str) -> String {
+        let mut text = format!("HTTP/1.1 {}\r\nConnection: close\r\n", status);
+        for header in headers {
+            text.push_str(header);
+            text.push_str("\r\n");
+        }
+        text.push_str(&format!("Content-Length: {}\r\n\r\n{}", body.len(), body));
+        text
+    }
+
+    /// A page of `count` records, numbered from `first`.
+    fn page(first: usize, count: usize, total: Option<usize>) -> String {
+        let data: Vec<serde_json::Value> = (first..first + count)
+            .map(|id| serde_json::json!({ "id": id.to_string() }))
+            .collect();
+        let mut body = serde_json::json!({ "data": data });
+        if let Some(total) = total {
+            body["total_records"] = total.into();
+        }
+        response("200 OK", &[], &body.to_string())
+    }
+
+    fn http_settings() -> HttpSettings {
+        HttpSettings {
+            connect_timeout_secs: 5,
+            timeout_secs: 5,
+            retries: 2,
+            backoff_ms: 10,
+        }
+    }
+
+    fn api(server: &StubServer) -> CeloxisApi {
+        let http = http_settings();
+        CeloxisApi {
+            client: CeloxisApi::build_client("test-key", &http).unwrap(),
+            config: ApiConfig {
+                base_url: server.url.clone(),
+                key_location: auth::KeyLocation {
+                    env_var: "CELOXIS_TEST_API_KEY".to_string(),
+                    key_file: None,
+                    saved_path: PathBuf::from("unused"),
+                },
+                cache_path: PathBuf::from("unused"),
+                username: None,
+                time_code: None,
+                rounding: Rounding::default(),
+                profile: None,
+                cache_ttl: Duration::hours(24),
+                offline: false,
+                http,
+            },
+            cache: None,
+        }
+    }
+
+    fn offsets(server: &StubServer) -> Vec<String> {
+        server
+            .requests()
+            .iter()
+            .map(|request| {
+                let offset = request.split("offset=").nth(1).unwrap_or_default();
+                offset.split(['&', ' ']).next().unwrap().to_string()
+            })
+            .collect()
+    }
+
+    fn get_all(server: &StubServer) -> Vec<serde_json::Value> {
+        api(server).get_all("projects", "{}", "projects").unwrap()
+    }
+
+    #[test]
+    fn get_all_stops_when_total_records_arrived() {
+        let server = StubServer::start(vec![
+            page(0, 100, Some(250)),
+            page(100, 100, Some(250)),
+            page(200, 50, Some(250)),
+        ]);
+        let records = get_all(&server);
+        assert_eq!(records.len(), 250);
+        assert_eq!(records[249]["id"], "249");
+        assert_eq!(offsets(&server), vec!["0", "100", "200"]);
+    }
+
+    #[test]
+    fn get_all_does_not_ask_past_a_full_last_page() {
+        let server = StubServer::start(vec![page(0, 100, Some(200)), page(100, 100, Some(200))]);
+        assert_eq!(get_all(&server).len(), 200);
+        assert_eq!(offsets(&server), vec!["0", "100"]);
+    }
+
+    #[test]
+    fn get_all_without_total_records_stops_at_a_short_page() {
+        let server = StubServer::start(vec![page(0, 100, None), page(100, 30, None)]);
+        assert_eq!(get_all(&server).len(), 130);
+        assert_eq!(offsets(&server), vec!["0", "100"]);
+    }
+
+    #[test]
+    fn get_all_stops_at_an_empty_page() {
+        // Fewer records than announced, the empty page ends it
+        let server = StubServer::start(vec![page(0, 100, Some(300)), page(100, 0, Some(300))]);
+        assert_eq!(get_all(&server).len(), 100);
+        assert_eq!(offsets(&server), vec!["0", "100"]);
+
+        let server = StubServer::start(vec![page(0, 100, None), page(100, 0, None)]);
+        assert_eq!(get_all(&server).len(), 100);
+        assert_eq!(offsets(&server), vec!["0", "100"]);
+    }
+
+    fn get(server: &StubServer, http: &HttpSettings) -> Response {
+        let client = CeloxisApi::build_client("test-key", http).unwrap();
+        let url = format!("{}/projects", server.url);
+        send_with_retry(http, Retry::Always, || client.get(&url)).unwrap()
+    }
+
+    fn post(server: &StubServer, http: &HttpSettings) -> Response {
+        let client = CeloxisApi::build_client("test-key", http).unwrap();
+        let url = format!("{}/timeEntries", server.url);
+        send_with_retry(http, Retry::IfNotSent, || client.post(&url).body("[]")).unwrap()
+    }
+
+    #[test]
+    fn rate_limited_request_waits_for_retry_after() {
+        let server = StubServer::start(vec![
+            response("429 Too Many Requests", &["Retry-After: 1"], ""),
+            page(0, 1, Some(1)),
+        ]);
+        let started = std::time::Instant::now();
+        let response = get(&server, &http_settings());
+        assert_eq!(response.status().as_u16(), 200);
+        assert_eq!(server.requests().len(), 2);
+        // Much longer than the backoff of 10ms
+        assert!(started.elapsed() >= StdDuration::from_secs(1));
+    }
+
+    #[test]
+    fn retry_after_in_seconds_or_as_http_date() {
+        assert_eq!(parse_retry_after(" 7 "), Some(StdDuration::from_secs(7)));
+        assert_eq!(
+            parse_retry_after("86400"),
+            Some(StdDuration::from_secs(MAX_RETRY_AFTER))
+        );
+
+        let in_a_minute = (Utc::now() + Duration::seconds(60)).to_rfc2822();
+        let delay = parse_retry_after(&in_a_minute.replace("+0000", "GMT")).unwrap();
+        assert!(delay > StdDuration::from_secs(55) && delay <= StdDuration::from_secs(60));
+        assert_eq!(
+            parse_retry_after("Sun, 06 Nov 1994 08:49:37 GMT"),
+            Some(StdDuration::ZERO)
+        );
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+
+    #[test]
+    fn rate_limit_error_reports_the_retry_after_date() {
+        let in_a_minute = (Utc::now() + Duration::seconds(60)).to_rfc2822();
+        let header = format!("Retry-After: {}", in_a_minute.replace("+0000", "GMT"));
+        let server = StubServer::start(vec![response("429 Too Many Requests", &[&header], "")]);
+        let http = HttpSettings {
+            retries: 0,
+            ..http_settings()
+        };
+
+        let error = CeloxisError::check_status(get(&server, &http)).unwrap_err();
+        match error {
+            CeloxisError::RateLimited {
+                retry_after: Some(seconds),
+            } => assert!((55..=60).contains(&seconds)),
+            other => panic!("expected a rate limit with a delay, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn server_errors_on_reads_are_retried() {
+        let server = StubServer::start(vec![
+            response("503 Service Unavailable", &[], ""),
+            response("500 Internal Server Error", &[], ""),
+            page(0, 1, Some(1)),
+        ]);
+        let response = get(&server, &http_settings());
+        assert_eq!(response.status().as_u16(), 200);
+        assert_eq!(server.requests().len(), 3);
+    }
+
+    #[test]
+    fn last_server_error_is_returned_once_retries_run_out() {
+        let server = StubServer::start(vec![
+            response("502 Bad Gateway", &[], ""),
+            response("502 Bad Gateway", &[], ""),
+            response("502 Bad Gateway", &[], ""),
+        ]);
+        let response = get(&server, &http_settings());
+        assert_eq!(response.status().as_u16(), 502);
+        assert_eq!(server.requests().len(), 3);
+    }
+
+    #[test]
+    fn writes_are_not_retried_after_a_server_error() {
+        let server = StubServer::start(vec![
+            response("500 Internal Server Error", &[], ""),
+            response("201 Created", &[], "[]"),
+        ]);
+        let response = post(&server, &http_settings());
+        assert_eq!(response.status().as_u16(), 500);
+        assert_eq!(server.requests(), vec!["POST /timeEntries HTTP/1.1"]);
+    }
+
+    #[test]
+    fn rate_limited_writes_are_retried() {
+        let server = StubServer::start(vec![
+            response("429 Too Many Requests", &["Retry-After: 0"], ""),
+            response("201 Created", &[], "[]"),
+        ]);
+        let response = post(&server, &http_settings());
+        assert_eq!(response.status().as_u16(), 201);
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    fn time_entry(date: &str, task: &str, hours: f64) -> CeloxisTimeEntry {
+        CeloxisTimeEntry {
+            date: date.to_string(),
+            hours,
+            time_code: "Regular".to_string(),
+            user: "me".to_string(),
+            task: task.to_string(),
+            state: 0,
+            comments: String::new(),
+        }
+    }
+
+    fn submitted(id: &str) -> EntryOutcome {
+        EntryOutcome::Submitted {
+            id: Some(id.to_string()),
+        }
+    }
+
+    #[test]
+    fn outcomes_follow_the_index_of_each_record() {
+        let entries = [
+            time_entry("2026-03-02", "t1", 1.0),
+            time_entry("2026-03-03", "t1", 2.0),
+            time_entry("2026-03-04", "t1", 3.0),
+        ];
+        let body = r#"[{"index": 2, "id": "c"}, {"index": 0, "error": "Task is closed"}]"#;
+        let outcomes = CeloxisApi::parse_outcomes(body, &entries);
+        assert_eq!(
+            outcomes,
+            vec![
+                EntryOutcome::Failed {
+                    reason: "Task is closed".to_string()
+                },
+                EntryOutcome::Unknown,
+                submitted("c"),
+            ]
+        );
+    }
+
+    #[test]
+    fn outcomes_follow_the_position_with_one_record_per_entry() {
+        let entries = [
+            time_entry("2026-03-02", "t1", 1.0),
+            time_entry("2026-03-03", "t2", 2.0),
+        ];
+        let body = r#"{"data": [{"id": 7}, {"id": "8"}]}"#;
+        let outcomes = CeloxisApi::parse_outcomes(body, &entries);
+        assert_eq!(outcomes, vec![submitted("7"), submitted("8")]);
+    }
+
+    #[test]
+    fn outcomes_follow_date_task_and_hours_otherwise() {
+        let entries = [
+            time_entry("2026-03-02", "t1", 1.5),
+            time_entry("2026-03-02", "t2", 1.5),
+            time_entry("2026-03-03", "t2", 0.25),
+        ];
+        let body = r#"[
+            {"id": "b", "date": "2026-03-03T00:00:00", "task": "t2", "hours": 0.25},
+            {"id": "a", "date": "2026-03-02", "task": "t2", "hours": 1.5}
+        ]"#;
+        let outcomes = CeloxisApi::parse_outcomes(body, &entries);
+        assert_eq!(
+            outcomes,
+            vec![EntryOutcome::Unknown, submitted("a"), submitted("b")]
+        );
+    }
+
+    #[test]
+    fn outcomes_are_unknown_without_records() {
+        let entries = [time_entry("2026-03-02", "t1", 1.0)];
+        for body in ["", "OK", r#"{"status": "ok"}"#, "[]"] {
+            let outcomes = CeloxisApi::parse_outcomes(body, &entries);
+            assert_eq!(outcomes, vec![EntryOutcome::Unknown], "body {:?}", body);
+        }
     }
 }
@@ -0,0 +1,241 @@
+use chrono::{Local, NaiveDate};
+use clap::{Args, Parser, Subcommand};
+use std::path::PathBuf;
+
+use crate::export::ExportFormat;
+use crate::grouping::GroupStrategy;
+use crate::timew::{OpenIntervals, TagMode};
+
+const EXIT_CODES: &str = "\
+Exit codes:
+  1  any other error
+  2  invalid command line
+  3  Celoxis rejected the API key
+  4  Celoxis rejected the submitted entries
+  5  Celoxis is rate limiting requests
+  6  Celoxis could not be reached
+  7  unexpected response from Celoxis";
+
+/// Upload Timewarrior intervals to Celoxis as time entries.
+///
+/// Without a subcommand the interactive wizard is started.
+#[derive(Debug, Parser)]
+#[command(name = "tw-upload", version, about, after_help = EXIT_CODES)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    #[command(flatten)]
+    pub global: GlobalArgs,
+}
+
+/// Options shared by the wizard and every subcommand.
+#[derive(Debug, Clone, Args)]
+pub struct GlobalArgs {
+    /// Tag written back into Timewarrior for submitted intervals
+    #[arg(long, global = true, value_enum, default_value_t = TagMode::Submitted)]
+    pub tag_intervals: TagMode,
+
+    /// What to do with an interval that is still being tracked, defaults to
+    /// open_intervals from config.toml or block
+    #[arg(long, global = true, value_enum, value_name = "POLICY")]
+    pub open_intervals: Option<OpenIntervals>,
+
+    /// What makes intervals belong to the same group, defaults to grouping.strategy from
+    /// config.toml or exact
+    #[arg(long, global = true, value_enum, value_name = "STRATEGY")]
+    pub group_by: Option<GroupStrategy>,
+
+    /// Read intervals from a Timewarrior extension report on stdin, e.g. when
+    /// installed as ~/.timewarrior/extensions/celoxis and run via `timew report celoxis :week`.
+    /// Timewarrior passes no arguments, so without any a report on stdin turns this on
+    #[arg(long, global = true)]
+    pub report: bool,
+
+    /// Timewarrior database to read, takes precedence over TIMEWARRIORDB and data_dir
+    /// from config.toml
+    #[arg(long, global = true, value_name = "PATH")]
+    pub data_dir: Option<PathBuf>,
+
+    /// Mapping rules file, defaults to rules_file from config.toml or celoxis_rules.json
+    /// in the Timewarrior directory
+    #[arg(long, global = true, value_name = "PATH")]
+    pub rules: Option<PathBuf>,
+
+    /// Run the whole flow but export the prepared time entries instead of submitting them
+    #[arg(long, global = true)]
+    pub dry_run: bool,
+
+    /// Format of the entries exported by --dry-run
+    #[arg(long, global = true, value_enum, default_value_t = ExportFormat::Table)]
+    pub format: ExportFormat,
+
+    /// Write the entries exported by --dry-run to this file instead of stdout. Without it
+    /// all other output goes to stderr, so stdout holds just the entries
+    #[arg(long, global = true, value_name = "PATH")]
+    pub output: Option<PathBuf>,
+
+    /// Read the Celoxis API key from this file; CELOXIS_API_KEY takes precedence
+    #[arg(long, global = true, value_name = "PATH")]
+    pub api_key_file: Option<PathBuf>,
+
+    /// Celoxis profile from config.toml, selecting the tenant, API key and cache
+    #[arg(long, global = true, value_name = "NAME")]
+    pub profile: Option<String>,
+
+    /// Work only from cached projects and tasks, nothing is fetched or submitted
+    #[arg(long, global = true)]
+    pub offline: bool,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// List the Timewarrior intervals in a date range
+    List(RangeArgs),
+    /// Show the intervals in a date range grouped by tags
+    Group {
+        #[command(flatten)]
+        range: RangeArgs,
+        /// Also print the individual intervals of every group
+        #[arg(long)]
+        intervals: bool,
+        /// Include intervals that were already submitted to Celoxis
+        #[arg(long)]
+        all: bool,
+    },
+    /// Submit grouped intervals to a Celoxis task without prompting
+    Submit(SubmitArgs),
+    /// Continue the wizard session saved in the draft
+    Resume,
+    /// List active Celoxis projects
+    Projects {
+        /// Fetch the project list from Celoxis instead of the cache
+        #[arg(long)]
+        refresh: bool,
+    },
+    /// List the tasks of a Celoxis project
+    Tasks {
+        /// Celoxis project id
+        #[arg(long)]
+        project: String,
+        /// Fetch the task list from Celoxis instead of the cache
+        #[arg(long)]
+        refresh: bool,
+    },
+    /// Inspect, refresh or clear the local Celoxis cache
+    Cache {
+        #[command(subcommand)]
+        action: CacheAction,
+    },
+    /// Manage the saved Celoxis API key
+    Auth {
+        #[command(subcommand)]
+        action: AuthAction,
+    },
+    /// Read or change settings in config.toml
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigAction {
+    /// Print a setting, or the whole file without a key
+    Get {
+        /// Dotted key, e.g. time_code, rounding.increment or profiles.acme.base_url
+        key: Option<String>,
+    },
+    /// Change a setting
+    Set {
+        /// Dotted key, e.g. time_code, rounding.increment or profiles.acme.base_url
+        key: String,
+        value: String,
+    },
+    /// Open config.toml in $VISUAL or $EDITOR
+    Edit,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum AuthAction {
+    /// Ask for an API key, validate it against Celoxis and save it
+    Set,
+    /// Show where the API key in use comes from
+    Show,
+    /// Delete the saved API key
+    Clear,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum CacheAction {
+    /// Show the cache location, what it contains and how old it is
+    Status,
+    /// Fetch the projects and every cached task list again
+    Refresh {
+        /// Only refresh the tasks of this Celoxis project
+        #[arg(long)]
+        project: Option<String>,
+    },
+    /// Delete all cached projects and tasks
+    Clear,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct RangeArgs {
+    /// First day to include (YYYY-MM-DD), defaults to today
+    #[arg(long, value_parser = parse_date)]
+    pub from: Option<NaiveDate>,
+    /// Last day to include (YYYY-MM-DD), defaults to --from
+    #[arg(long, value_parser = parse_date)]
+    pub to: Option<NaiveDate>,
+}
+
+impl RangeArgs {
+    pub fn is_set(&self) -> bool {
+        self.from.is_some() || self.to.is_some()
+    }
+
+    pub fn resolve(&self) -> Result<(NaiveDate, NaiveDate), String> {
+        let start = self.from.unwrap_or_else(|| Local::now().date_naive());
+        let end = self.to.unwrap_or(start);
+        if end < start {
+            return Err(format!("--to ({}) is before --from ({})", end, start));
+        }
+        Ok((start, end))
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct SubmitArgs {
+    #[command(flatten)]
+    pub range: RangeArgs,
+    /// Celoxis project id to book the time against
+    #[arg(long)]
+    pub project: String,
+    /// Celoxis task id to book the time against
+    #[arg(long)]
+    pub task: String,
+    /// Work summary used as the comment of every entry, defaults to each day's annotations
+    #[arg(long)]
+    pub summary: Option<String>,
+    /// Only submit groups containing this tag (repeatable)
+    #[arg(long = "tag")]
+    pub tags: Vec<String>,
+    /// Only submit these group numbers as printed by `group` (repeatable)
+    #[arg(long = "group")]
+    pub groups: Vec<usize>,
+    /// Time code to use instead of the saved default
+    #[arg(long)]
+    pub time_code: Option<String>,
+    /// Trim overlapping intervals so their time is only submitted once
+    #[arg(long)]
+    pub trim_overlaps: bool,
+    /// Submit without asking for confirmation
+    #[arg(short, long)]
+    pub yes: bool,
+}
+
+fn parse_date(value: &str) -> Result<NaiveDate, String> {
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map_err(|e| format!("invalid date '{}': {} (expected YYYY-MM-DD)", value, e))
+}
@@ -0,0 +1,382 @@
+use chrono::{DateTime, Utc};
+use inquire::Confirm;
+use std::error::Error;
+use std::fs;
+
+use super::{submit_assignments, CeloxisData, DateRange, GroupedEntry, TaskAssignment, TimeData};
+use crate::auth;
+use crate::config::{self, Config};
+use crate::error::CeloxisError;
+use crate::grouping::GroupKey;
+use crate::celoxis::{self, CeloxisApi};
+use crate::cli::{AuthAction, CacheAction, ConfigAction, GlobalArgs, RangeArgs, SubmitArgs};
+
+fn date_range(args: &RangeArgs) -> Result<DateRange, Box<dyn Error>> {
+    let (start, end) = args.resolve()?;
+    Ok(DateRange { start, end })
+}
+
+fn load_time_data(
+    args: &RangeArgs,
+    global: &GlobalArgs,
+) -> Result<(TimeData, DateRange), Box<dyn Error>> {
+    // In report mode Timewarrior already resolved a range, explicit flags still win
+    let range = if args.is_set() || !global.report {
+        Some(date_range(args)?)
+    } else {
+        None
+    };
+    TimeData::load(range, global)
+}
+
+fn load_groups(
+    args: &RangeArgs,
+    global: &GlobalArgs,
+    include_submitted: bool,
+) -> Result<(TimeData, DateRange, Vec<GroupedEntry>), Box<dyn Error>> {
+    let (time_data, range) = load_time_data(args, global)?;
+    let filtered_entries = if include_submitted {
+        time_data.filter_by_date_range(&range)
+    } else {
+        time_data.pending_entries(&range)
+    };
+    let groups = time_data.group_entries_by_tags(filtered_entries);
+    Ok((time_data, range, groups))
+}
+
+pub fn list(args: &RangeArgs, global: &GlobalArgs) -> Result<(), Box<dyn Error>> {
+    let (time_data, range) = load_time_data(args, global)?;
+
+    println!("Intervals from {:?}", time_data.data_dir);
+    for entry in time_data.filter_by_date_range(&range) {
+        // Intervals crossing midnight are listed once per day
+        let (start, end) = entry.span();
+        let end = match end {
+            Some(end) => time_data.zone.format(end, "%H:%M"),
+            None => "now".to_string(),
+        };
+        let minutes = entry.minutes();
+
+        print!(
+            "{:<10} {} - {:<5} {:>3}h{:02}m  {}",
+            entry.id,
+            time_data.zone.format(start, "%Y-%m-%d %H:%M"),
+            end,
+            minutes / 60,
+            minutes % 60,
+            entry.tags.join(" ")
+        );
+        if let Some(annotation) = &entry.annotation {
+            print!("  # {}", annotation);
+        }
+        if entry.submitted {
+            match &entry.celoxis_id {
+                Some(id) => print!("  [Submitted: {}]", id),
+                None => print!("  [Submitted]"),
+            }
+        } else if time_data.ledger.is_unconfirmed(entry) {
+            print!("  [Unconfirmed]");
+        }
+        println!();
+    }
+
+    Ok(())
+}
+
+pub fn group(
+    args: &RangeArgs,
+    intervals: bool,
+    all: bool,
+    global: &GlobalArgs,
+) -> Result<(), Box<dyn Error>> {
+    let (time_data, range, groups) = load_groups(args, global, all)?;
+
+    if groups.is_empty() {
+        println!("No entries found between {} and {}", range.start, range.end);
+        return Ok(());
+    }
+
+    if !intervals {
+        TimeData::display_grouped_entries(&groups);
+        return Ok(());
+    }
+
+    for (idx, group) in groups.iter().enumerate() {
+        println!("\nGroup {} - {}", idx + 1, group.key);
+        let mut dates: Vec<_> = group.entries.keys().collect();
+        dates.sort();
+        for date in dates {
+            println!("  Date: {}", date);
+            for entry in &group.entries[date] {
+                let (start, end) = entry.span();
+                let duration = end.map_or_else(
+                    || "Ongoing".to_string(),
+                    |_| format!("{} minutes", entry.minutes()),
+                );
+                println!(
+                    "    - {} {} ({}){}",
+                    entry.id,
+                    time_data.zone.format(start, "%H:%M"),
+                    duration,
+                    if entry.submitted { " [Submitted]" } else { "" }
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+pub fn submit(args: &SubmitArgs, global: &GlobalArgs) -> Result<(), Box<dyn Error>> {
+    let (mut time_data, range) = load_time_data(&args.range, global)?;
+
+    // Groups are numbered as `group` prints them, before intervals are left out or trimmed
+    let numbered: Vec<GroupKey> = time_data
+        .group_entries_by_tags(time_data.pending_entries(&range))
+        .into_iter()
+        .map(|group| group.key)
+        .collect();
+    if let Some(number) = args.groups.iter().find(|&&n| n == 0 || n > numbered.len()) {
+        return Err(format!(
+            "There is no group {} ({} groups found)",
+            number,
+            numbered.len()
+        )
+        .into());
+    }
+    let keys: Vec<&GroupKey> = args.groups.iter().map(|n| &numbered[n - 1]).collect();
+
+    time_data.settle_open_intervals(&range, global)?;
+    time_data.check_intervals(Some(args.trim_overlaps))?;
+    let pending = time_data
+        .filter_by_date_range(&range)
+        .into_iter()
+        .filter(|entry| time_data.is_pending(entry))
+        .collect();
+
+    let selected: Vec<GroupedEntry> = time_data
+        .group_entries_by_tags(pending)
+        .into_iter()
+        .filter(|group| keys.is_empty() || keys.contains(&&group.key))
+        .filter(|group| args.tags.iter().all(|tag| group.tags.contains(tag)))
+        .collect();
+
+    if selected.is_empty() {
+        return Err(format!(
+            "No groups between {} and {} match the given filters",
+            range.start, range.end
+        )
+        .into());
+    }
+
+    let mut celoxis = CeloxisData::new(global)?;
+    let mut user_prefs = time_data.user_prefs(&mut celoxis.api)?;
+    if let Some(time_code) = &args.time_code {
+        user_prefs.time_code = time_code.clone();
+    }
+
+    let project = celoxis.find_project(&args.project)?;
+    let task = celoxis.find_task(&project.id, &args.task)?;
+    let assignment = TaskAssignment::new(
+        selected,
+        project,
+        task,
+        args.summary.clone().unwrap_or_default(),
+        &user_prefs,
+    );
+
+    let mut missing: Vec<String> = assignment
+        .to_celoxis_entries()
+        .into_iter()
+        .filter(|entry| entry.comments.trim().is_empty())
+        .map(|entry| entry.date)
+        .collect();
+    if !missing.is_empty() {
+        missing.sort();
+        return Err(format!(
+            "No --summary given and no annotations to use as comments on {}",
+            missing.join(", ")
+        )
+        .into());
+    }
+    assignment.display();
+
+    if !args.yes {
+        let question = if global.dry_run {
+            "Export this assignment without submitting it (dry run)?"
+        } else {
+            "Submit this assignment to Celoxis?"
+        };
+        let confirm_submit = Confirm::new(question).with_default(true).prompt()?;
+        if !confirm_submit {
+            status!("Submission cancelled.");
+            return Ok(());
+        }
+    }
+
+    let failed = submit_assignments(
+        &mut celoxis.api,
+        &mut time_data.ledger,
+        &time_data.data_dir,
+        &[assignment],
+        global,
+    )?;
+    if !failed.is_empty() {
+        // Running it again submits only these, the rest is in the ledger now
+        let dates: Vec<String> = failed
+            .iter()
+            .flat_map(TaskAssignment::dates)
+            .map(|date| date.to_string())
+            .collect();
+        return Err(CeloxisError::Validation {
+            message: format!("entries on {} were not saved", dates.join(", ")),
+            entries: Vec::new(),
+        }
+        .into());
+    }
+
+    Ok(())
+}
+
+pub fn projects(refresh: bool, global: &GlobalArgs) -> Result<(), Box<dyn Error>> {
+    let mut api = CeloxisApi::new(config::api_config(global)?)?;
+    let mut projects = api.get_projects(refresh)?;
+    projects.sort_by(|a, b| a.name.cmp(&b.name));
+
+    for project in projects {
+        println!("{} - {}", project.id, project.name);
+    }
+
+    Ok(())
+}
+
+pub fn tasks(project_id: &str, refresh: bool, global: &GlobalArgs) -> Result<(), Box<dyn Error>> {
+    let mut api = CeloxisApi::new(config::api_config(global)?)?;
+    let tasks = api.get_tasks(project_id, refresh)?;
+
+    if let Some(project) = api.get_cached_project(project_id) {
+        println!("Tasks of {} - {}", project.id, project.name);
+    }
+    for task in tasks {
+        println!("{} - {}", task.id, task.name);
+    }
+
+    Ok(())
+}
+
+fn describe_age(api: &CeloxisApi, updated: Option<DateTime<Utc>>) -> String {
+    match updated {
+        Some(updated) if api.is_stale(Some(updated)) => {
+            format!("{} (stale)", celoxis::format_age(updated))
+        }
+        Some(updated) => celoxis::format_age(updated),
+        None => "never".to_string(),
+    }
+}
+
+pub fn cache(action: &CacheAction, global: &GlobalArgs) -> Result<(), Box<dyn Error>> {
+    let mut api = CeloxisApi::new(config::api_config(global)?)?;
+
+    match action {
+        CacheAction::Status => {
+            println!("Cache file: {:?}", api.cache_path());
+            println!("Expires after: {} hours", api.cache_ttl().num_hours());
+            let projects = api.get_cached_projects().len();
+            println!(
+                "Projects: {}, fetched {}",
+                projects,
+                describe_age(&api, api.projects_updated())
+            );
+            for project_id in api.cached_task_projects() {
+                let name = api
+                    .get_cached_project(&project_id)
+                    .map(|project| project.name.clone())
+                    .unwrap_or_default();
+                let tasks = api.get_cached_tasks(&project_id).map_or(0, Vec::len);
+                println!(
+                    "  {} - {}: {} tasks, fetched {}",
+                    project_id,
+                    name,
+                    tasks,
+                    describe_age(&api, api.tasks_updated(&project_id))
+                );
+            }
+        }
+        CacheAction::Refresh { project: Some(project_id) } => {
+            let tasks = api.get_tasks(project_id, true)?;
+            println!("Fetched {} tasks of project {}", tasks.len(), project_id);
+        }
+        CacheAction::Refresh { project: None } => {
+            let projects = api.get_projects(true)?;
+            println!("Fetched {} projects", projects.len());
+            for project_id in api.cached_task_projects() {
+                let tasks = api.get_tasks(&project_id, true)?;
+                println!("Fetched {} tasks of project {}", tasks.len(), project_id);
+            }
+        }
+        CacheAction::Clear => {
+            api.clear_cache()?;
+            println!("Cleared cached projects and tasks");
+        }
+    }
+
+    Ok(())
+}
+
+pub fn auth(action: &AuthAction, global: &GlobalArgs) -> Result<(), Box<dyn Error>> {
+    let api_config = config::api_config(global)?;
+    let location = &api_config.key_location;
+
+    match action {
+        AuthAction::Set => {
+            CeloxisApi::prompt_api_key(&api_config)?;
+            if std::env::var(&location.env_var).is_ok() {
+                println!(
+                    "Note: {} is set and takes precedence over the saved key",
+                    location.env_var
+                );
+            }
+        }
+        AuthAction::Show => match auth::resolve_api_key(location)? {
+            Some((key, source)) => {
+                println!("API key: {}", auth::mask_key(&key));
+                println!("Source: {}", source);
+            }
+            None => println!("No API key configured, run `tw-upload auth set`"),
+        },
+        AuthAction::Clear => {
+            if auth::clear_api_key(location)? {
+                println!("Removed the saved API key from {:?}", location.saved_path);
+            } else {
+                println!("No saved API key to remove");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+pub fn config(action: &ConfigAction) -> Result<(), Box<dyn Error>> {
+    match action {
+        ConfigAction::Get { key: Some(key) } => match config::get(key)? {
+            Some(toml::Value::String(value)) => println!("{}", value),
+            Some(toml::Value::Table(table)) => print!("{}", toml::to_string_pretty(&table)?),
+            Some(value) => println!("{}", value),
+            None => return Err(format!("'{}' is not set", key).into()),
+        },
+        ConfigAction::Get { key: None } => {
+            let path = Config::path()?;
+            println!("# {}", path.display());
+            if path.exists() {
+                print!("{}", fs::read_to_string(&path)?);
+            }
+        }
+        ConfigAction::Set { key, value } => {
+            config::set(key, value)?;
+            println!("Set {} in {:?}", key, Config::path()?);
+        }
+        ConfigAction::Edit => config::edit()?,
+    }
+
+    Ok(())
+}
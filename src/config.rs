@@ -0,0 +1,553 @@
+use chrono::{Duration, NaiveTime};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+use toml_edit::{DocumentMut, Item, TableLike};
+
+use crate::auth::{self, KeyLocation};
+use crate::celoxis::{ApiConfig, DEFAULT_BASE_URL};
+use crate::cli::GlobalArgs;
+use crate::grouping::Grouping;
+use crate::rules::MappingRule;
+use crate::timew::OpenIntervals;
+
+const CONFIG_FILE: &str = "config.toml";
+const CACHE_FILE: &str = "celoxis_cache.json";
+
+/// Settings for one Celoxis tenant, e.g.
+///
+/// ```toml
+/// default_profile = "acme"
+///
+/// [profiles.acme]
+/// base_url = "https://celoxis.acme.example/psa/api/v2"
+/// api_key_env = "ACME_CELOXIS_API_KEY"
+/// username = "jdoe"
+/// time_code = "development"
+/// ```
+///
+/// Everything is optional and falls back to the defaults used without a profile.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    pub base_url: Option<String>,
+    /// Environment variable holding the API key instead of CELOXIS_API_KEY
+    pub api_key_env: Option<String>,
+    pub api_key_file: Option<PathBuf>,
+    pub username: Option<String>,
+    pub time_code: Option<String>,
+    pub cache_file: Option<PathBuf>,
+}
+
+/// Which way tracked time is rounded to the increment.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RoundingMode {
+    #[default]
+    Nearest,
+    Up,
+    Down,
+}
+
+/// What gets rounded before the hours are submitted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RoundingLevel {
+    /// Every interval on its own
+    Interval,
+    /// The time of an assignment on one day
+    #[default]
+    Day,
+    /// The total of an assignment, spread over its days
+    Assignment,
+}
+
+/// How tracked time is rounded before it is submitted, e.g. up to the next 6 minutes
+/// per interval with at least half an hour per entry:
+///
+/// ```toml
+/// [rounding]
+/// increment = 6
+/// mode = "up"
+/// level = "interval"
+/// minimum = 30
+/// ```
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Rounding {
+    /// Round to a multiple of this many minutes, 0 disables rounding
+    pub increment: u32,
+    pub mode: RoundingMode,
+    /// Least minutes charged for a time entry with anything tracked, applied after rounding
+    pub minimum: u32,
+    pub level: RoundingLevel,
+}
+
+impl Rounding {
+    /// Rounds to the increment. Time can round down to nothing, `minimum` is what keeps
+    /// short entries from being lost.
+    pub fn apply(&self, minutes: i64) -> i64 {
+        let increment = i64::from(self.increment);
+        if minutes <= 0 || increment == 0 {
+            return minutes;
+        }
+
+        let steps = match self.mode {
+            RoundingMode::Nearest => (minutes + increment / 2) / increment,
+            RoundingMode::Up => (minutes + increment - 1) / increment,
+            RoundingMode::Down => minutes / increment,
+        };
+        steps * increment
+    }
+
+    /// Raises the rounded minutes of an entry to the minimum, if any time was tracked.
+    pub fn at_least_minimum(&self, tracked: i64, rounded: i64) -> i64 {
+        if tracked > 0 {
+            rounded.max(i64::from(self.minimum))
+        } else {
+            rounded
+        }
+    }
+}
+
+fn default_ttl_hours() -> u32 {
+    24
+}
+
+/// How long cached projects and tasks are used before they are fetched again.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CacheSettings {
+    #[serde(default = "default_ttl_hours")]
+    pub ttl_hours: u32,
+    /// Always work from the cache, as if --offline was given
+    #[serde(default)]
+    pub offline: bool,
+}
+
+impl Default for CacheSettings {
+    fn default() -> Self {
+        Self {
+            ttl_hours: default_ttl_hours(),
+            offline: false,
+        }
+    }
+}
+
+/// Timeouts and retries for requests to Celoxis.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpSettings {
+    /// Seconds to wait for a connection to Celoxis
+    pub connect_timeout_secs: u64,
+    /// Seconds to wait for a whole request, including reading the response
+    pub timeout_secs: u64,
+    /// How often a failed request is tried again
+    pub retries: u32,
+    /// Delay before the first retry in milliseconds, doubled for every further one
+    pub backoff_ms: u64,
+}
+
+impl Default for HttpSettings {
+    fn default() -> Self {
+        Self {
+            connect_timeout_secs: 10,
+            timeout_secs: 60,
+            retries: 3,
+            backoff_ms: 500,
+        }
+    }
+}
+
+/// Thresholds of the checks run over the intervals before they are assigned. A
+/// threshold of 0 turns its check off.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ValidationSettings {
+    /// Report gaps between intervals longer than this many minutes within working hours
+    pub max_gap_minutes: u32,
+    pub work_start: NaiveTime,
+    pub work_end: NaiveTime,
+    /// Report intervals longer than this many hours
+    pub max_interval_hours: f64,
+    /// Report days with more than this many hours tracked
+    pub max_day_hours: f64,
+}
+
+impl Default for ValidationSettings {
+    fn default() -> Self {
+        Self {
+            max_gap_minutes: 60,
+            work_start: NaiveTime::from_hms_opt(9, 0, 0).unwrap(),
+            work_end: NaiveTime::from_hms_opt(17, 0, 0).unwrap(),
+            max_interval_hours: 10.0,
+            max_day_hours: 10.0,
+        }
+    }
+}
+
+/// tw-upload's settings in `~/.config/tw-upload/config.toml`:
+///
+/// ```toml
+/// username = "jdoe"
+/// time_code = "engineering_labor"
+/// data_dir = "/home/jdoe/.timewarrior"
+/// timezone = "Europe/Berlin"
+/// open_intervals = "exclude"
+/// rules_file = "/home/jdoe/celoxis_rules.json"
+///
+/// [rounding]
+/// increment = 15
+/// level = "day"
+///
+/// [cache]
+/// ttl_hours = 24
+///
+/// [http]
+/// timeout_secs = 60
+/// retries = 3
+///
+/// [validation]
+/// max_gap_minutes = 60
+/// work_start = "09:00"
+/// work_end = "17:00"
+/// max_day_hours = 10
+///
+/// [grouping]
+/// strategy = "prefix"
+/// prefix = "project:"
+/// ignore_tags = ["meeting"]
+///
+/// [[rules]]
+/// match = { project = "acme" }
+/// project_id = "1234"
+/// task_id = "5678"
+/// ```
+///
+/// Inline `[[rules]]` are tried after the ones from the rules file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub username: Option<String>,
+    /// Time code used unless a rule or the command line picks another one
+    pub time_code: Option<String>,
+    /// Timewarrior directory to use instead of the detected one
+    pub data_dir: Option<PathBuf>,
+    /// Mapping rules file, defaults to celoxis_rules.json in the Timewarrior directory
+    pub rules_file: Option<PathBuf>,
+    /// Timezone whose midnight separates the days, defaults to the system's
+    pub timezone: Option<chrono_tz::Tz>,
+    /// What to do with an interval that is still being tracked, see --open-intervals
+    #[serde(default)]
+    pub open_intervals: OpenIntervals,
+    #[serde(default)]
+    pub rounding: Rounding,
+    #[serde(default)]
+    pub cache: CacheSettings,
+    #[serde(default)]
+    pub http: HttpSettings,
+    #[serde(default)]
+    pub validation: ValidationSettings,
+    #[serde(default)]
+    pub grouping: Grouping,
+    #[serde(default)]
+    pub rules: Vec<MappingRule>,
+    /// Profile used when --profile isn't given
+    pub default_profile: Option<String>,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+impl Config {
+    pub fn path() -> Result<PathBuf, Box<dyn Error>> {
+        Ok(auth::config_dir()?.join(CONFIG_FILE))
+    }
+
+    /// Loads the config file, a missing file means everything is left at its default.
+    pub fn load() -> Result<Self, Box<dyn Error>> {
+        let path = Self::path()?;
+        let table = read_table()?;
+        toml::Value::Table(table)
+            .try_into()
+            .map_err(|e| format!("Invalid config {:?}: {}", path, e).into())
+    }
+
+    /// The profile selected with --profile, or the default profile.
+    pub fn profile(&self, name: Option<&str>) -> Result<Option<(&str, &Profile)>, Box<dyn Error>> {
+        let Some(name) = name.or(self.default_profile.as_deref()) else {
+            return Ok(None);
+        };
+
+        match self.profiles.get_key_value(name) {
+            Some((name, profile)) => Ok(Some((name.as_str(), profile))),
+            None => Err(format!("Unknown profile '{}' in {:?}", name, Self::path()?).into()),
+        }
+    }
+}
+
+/// Builds the Celoxis connection settings for the profile selected on the command line.
+pub fn api_config(global: &GlobalArgs) -> Result<ApiConfig, Box<dyn Error>> {
+    let config = Config::load()?;
+    let selected = config.profile(global.profile.as_deref())?;
+    let (name, profile) = match selected {
+        Some((name, profile)) => (Some(name), profile.clone()),
+        None => (None, Profile::default()),
+    };
+
+    let mut key_location = KeyLocation::for_profile(name)?;
+    if let Some(env_var) = profile.api_key_env {
+        key_location.env_var = env_var;
+    }
+    key_location.key_file = global.api_key_file.clone().or(profile.api_key_file);
+
+    // Each profile gets its own cache so projects of different tenants don't mix
+    let cache_path = match profile.cache_file {
+        Some(path) => path,
+        None => {
+            let file_name = match name {
+                Some(name) => format!("celoxis_cache.{}.json", name),
+                None => CACHE_FILE.to_string(),
+            };
+            let path = ApiConfig::default_cache_path(&file_name);
+            ApiConfig::move_legacy_cache(&file_name, &path)?;
+            path
+        }
+    };
+
+    Ok(ApiConfig {
+        base_url: profile
+            .base_url
+            .as_deref()
+            .unwrap_or(DEFAULT_BASE_URL)
+            .trim_end_matches('/')
+            .to_string(),
+        key_location,
+        cache_path,
+        username: profile.username.or_else(|| config.username.clone()),
+        time_code: profile.time_code.or_else(|| config.time_code.clone()),
+        rounding: config.rounding,
+        profile: name.map(str::to_string),
+        cache_ttl: Duration::hours(i64::from(config.cache.ttl_hours)),
+        offline: global.offline || config.cache.offline,
+        http: config.http,
+    })
+}
+
+fn read_table() -> Result<toml::Table, Box<dyn Error>> {
+    let path = Config::path()?;
+    if !path.exists() {
+        return Ok(toml::Table::new());
+    }
+
+    let content = fs::read_to_string(&path)?;
+    content
+        .parse()
+        .map_err(|e| format!("Could not read {:?}: {}", path, e).into())
+}
+
+/// Reads the config file for editing, keeping its comments and layout.
+fn read_document() -> Result<DocumentMut, Box<dyn Error>> {
+    let path = Config::path()?;
+    if !path.exists() {
+        return Ok(DocumentMut::new());
+    }
+
+    let content = fs::read_to_string(&path)?;
+    content
+        .parse()
+        .map_err(|e| format!("Could not read {:?}: {}", path, e).into())
+}
+
+/// Writes the document back after checking it is still a valid config.
+fn write_document(document: &DocumentMut) -> Result<(), Box<dyn Error>> {
+    let path = Config::path()?;
+    let content = document.to_string();
+    toml::from_str::<Config>(&content)
+        .map_err(|e| format!("Refusing to write an invalid config: {}", e))?;
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&path, content)?;
+    Ok(())
+}
+
+/// Looks up a dotted key such as `rounding.increment` or `profiles.acme.base_url`.
+pub fn get(key: &str) -> Result<Option<toml::Value>, Box<dyn Error>> {
+    let mut value = &toml::Value::Table(read_table()?);
+    for part in key.split('.') {
+        match value.get(part) {
+            Some(next) => value = next,
+            None => return Ok(None),
+        }
+    }
+    Ok(Some(value.clone()))
+}
+
+/// Sets a dotted key. The value is read as TOML where possible, so `15` becomes a number
+/// and `true` a boolean, and is taken as a plain string otherwise. Comments and the order
+/// of the file are kept.
+pub fn set(key: &str, value: &str) -> Result<(), Box<dyn Error>> {
+    let value = value
+        .parse::<toml_edit::Value>()
+        .unwrap_or_else(|_| value.into());
+
+    let mut document = read_document()?;
+    set_value(&mut document, key, value)?;
+    write_document(&document)
+}
+
+fn set_value(
+    document: &mut DocumentMut,
+    key: &str,
+    mut value: toml_edit::Value,
+) -> Result<(), Box<dyn Error>> {
+    let mut parts: Vec<&str> = key.split('.').collect();
+    let last = parts
+        .pop()
+        .filter(|part| !part.is_empty())
+        .ok_or("Empty config key")?;
+
+    let mut current: &mut dyn TableLike = document.as_table_mut();
+    for part in parts {
+        current = current
+            .entry(part)
+            .or_insert_with(|| {
+                // Only the tables holding values get a header of their own
+                let mut table = toml_edit::Table::new();
+                table.set_implicit(true);
+                Item::Table(table)
+            })
+            .as_table_like_mut()
+            .ok_or_else(|| format!("'{}' in '{}' is not a table", part, key))?;
+    }
+
+    // Replaced in place so the comments around the old value stay
+    match current.get_mut(last).and_then(Item::as_value_mut) {
+        Some(old) => {
+            *value.decor_mut() = old.decor().clone();
+            *old = value;
+        }
+        None => {
+            current.insert(last, Item::Value(value));
+        }
+    }
+    Ok(())
+}
+
+/// Saves the username and time code, under the profile if one is in use.
+pub fn save_user_prefs(
+    profile: Option<&str>,
+    username: Option<&str>,
+    time_code: Option<&str>,
+) -> Result<PathBuf, Box<dyn Error>> {
+    let prefix = match profile {
+        Some(name) => format!("profiles.{}.", name),
+        None => String::new(),
+    };
+
+    let mut document = read_document()?;
+    if let Some(username) = username {
+        set_value(
+            &mut document,
+            &format!("{}username", prefix),
+            username.into(),
+        )?;
+    }
+    if let Some(time_code) = time_code {
+        set_value(
+            &mut document,
+            &format!("{}time_code", prefix),
+            time_code.into(),
+        )?;
+    }
+    write_document(&document)?;
+    Config::path()
+}
+
+/// Opens the config file in $VISUAL or $EDITOR and checks it afterwards.
+pub fn edit() -> Result<(), Box<dyn Error>> {
+    let path = Config::path()?;
+    if !path.exists() {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&path, "")?;
+    }
+
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+    let mut words = editor.split_whitespace();
+    let program = words.next().ok_or("$EDITOR is empty")?;
+
+    let status = process::Command::new(program)
+        .args(words)
+        .arg(&path)
+        .status()
+        .map_err(|e| format!("Could not start editor '{}': {}", editor, e))?;
+    if !status.success() {
+        return Err(format!("Editor '{}' exited with {}", editor, status).into());
+    }
+
+    Config::load()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"# Settings for tw-upload
+username = "jdoe" # Celoxis login
+
+[rounding]
+# Bill in tenths of an hour
+increment = 6
+mode = "up"
+
+[profiles.acme]
+base_url = "https://celoxis.acme.example/psa/api/v2"
+"#;
+
+    fn set(content: &str, key: &str, value: &str) -> String {
+        let mut document: DocumentMut = content.parse().unwrap();
+        let value = value.parse().unwrap_or_else(|_| value.into());
+        set_value(&mut document, key, value).unwrap();
+        document.to_string()
+    }
+
+    #[test]
+    fn changed_values_keep_comments_and_order() {
+        let content = set(CONFIG, "rounding.increment", "15");
+        assert_eq!(content, CONFIG.replace("increment = 6", "increment = 15"));
+
+        let content = set(CONFIG, "username", "jane");
+        assert_eq!(
+            content,
+            CONFIG.replace(r#"username = "jdoe""#, r#"username = "jane""#)
+        );
+    }
+
+    #[test]
+    fn new_keys_are_added_to_their_table() {
+        let content = set(CONFIG, "profiles.acme.time_code", "development");
+        assert_eq!(content, format!("{}time_code = \"development\"\n", CONFIG));
+    }
+
+    #[test]
+    fn new_tables_get_a_single_header() {
+        let content = set("", "profiles.globex.username", "jdoe");
+        assert_eq!(content, "[profiles.globex]\nusername = \"jdoe\"\n");
+    }
+
+    #[test]
+    fn values_inside_other_values_are_refused() {
+        let mut document: DocumentMut = CONFIG.parse().unwrap();
+        assert!(set_value(&mut document, "username.first", "jane".into()).is_err());
+        assert!(set_value(&mut document, "rounding.", "jane".into()).is_err());
+    }
+}
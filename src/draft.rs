@@ -0,0 +1,150 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::grouping::GroupKey;
+use crate::{DateRange, TaskAssignment};
+
+const DRAFT_FILE: &str = "draft.json";
+
+/// A group as it was when it got assigned, identified by its key.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DraftGroup {
+    pub tags: Vec<String>,
+    /// Missing in drafts saved before groups had keys other than their tags
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<GroupKey>,
+    /// Days of the group in this assignment, a group split across tasks has several parts
+    #[serde(default)]
+    pub dates: Vec<NaiveDate>,
+    /// Tracked minutes at the time, to notice intervals that changed since
+    pub minutes: i64,
+}
+
+impl DraftGroup {
+    pub fn group_key(&self) -> GroupKey {
+        self.key
+            .clone()
+            .unwrap_or_else(|| GroupKey::Tags(self.tags.clone()))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DraftAssignment {
+    pub groups: Vec<DraftGroup>,
+    pub project_id: String,
+    pub task_id: String,
+    pub summary: String,
+    pub time_code: String,
+    /// Minutes adjusted by hand per day
+    #[serde(default)]
+    pub adjusted: BTreeMap<NaiveDate, i64>,
+}
+
+/// The assignments of an unfinished wizard session. Groups that weren't assigned yet
+/// aren't stored, they come back from the Timewarrior data when the session is resumed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Draft {
+    pub start: NaiveDate,
+    pub end: NaiveDate,
+    pub saved_at: DateTime<Utc>,
+    pub assignments: Vec<DraftAssignment>,
+}
+
+impl Draft {
+    pub fn date_range(&self) -> DateRange {
+        DateRange {
+            start: self.start,
+            end: self.end,
+        }
+    }
+}
+
+/// Where the draft of the current session is kept, e.g. `~/.local/state/tw-upload/draft.json`.
+#[derive(Debug, Clone)]
+pub struct DraftFile {
+    path: PathBuf,
+}
+
+impl DraftFile {
+    pub fn new() -> Result<Self, Box<dyn Error>> {
+        let dir = dirs::state_dir()
+            .or_else(dirs::data_local_dir)
+            .ok_or("Could not determine a directory for the session draft")?;
+        Ok(Self {
+            path: dir.join("tw-upload").join(DRAFT_FILE),
+        })
+    }
+
+    pub fn load(&self) -> Result<Option<Draft>, Box<dyn Error>> {
+        if !self.path.exists() {
+            return Ok(None);
+        }
+
+        let content = fs::read_to_string(&self.path)?;
+        let draft = serde_json::from_str(&content)
+            .map_err(|e| format!("Could not read session draft {:?}: {}", self.path, e))?;
+        Ok(Some(draft))
+    }
+
+    /// Replaces the draft with the given assignments, without any there is nothing to keep.
+    pub fn save(
+        &self,
+        range: &DateRange,
+        assignments: &[TaskAssignment],
+    ) -> Result<(), Box<dyn Error>> {
+        if assignments.is_empty() {
+            return self.remove();
+        }
+
+        let draft = Draft {
+            start: range.start,
+            end: range.end,
+            saved_at: Utc::now(),
+            assignments: assignments
+                .iter()
+                .map(|assignment| DraftAssignment {
+                    groups: assignment
+                        .groups
+                        .iter()
+                        .map(|group| {
+                            let mut dates: Vec<NaiveDate> =
+                                group.total_duration.keys().copied().collect();
+                            dates.sort();
+                            DraftGroup {
+                                tags: group.tags.clone(),
+                                key: Some(group.key.clone()),
+                                dates,
+                                minutes: group.total_duration.values().sum(),
+                            }
+                        })
+                        .collect(),
+                    project_id: assignment.celoxis_project.id.clone(),
+                    task_id: assignment.celoxis_task.id.clone(),
+                    summary: assignment.summary.clone(),
+                    time_code: assignment.time_code.clone(),
+                    adjusted: assignment.adjusted.clone().into_iter().collect(),
+                })
+                .collect(),
+        };
+
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // Write to a temporary file first so an interrupted run cannot corrupt the draft
+        let tmp_path = self.path.with_extension("json.tmp");
+        fs::write(&tmp_path, serde_json::to_string_pretty(&draft)?)?;
+        fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+
+    pub fn remove(&self) -> Result<(), Box<dyn Error>> {
+        if self.path.exists() {
+            fs::remove_file(&self.path)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,203 @@
+use reqwest::blocking::Response;
+use reqwest::header::RETRY_AFTER;
+use reqwest::StatusCode;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::error::Error;
+use std::fmt;
+
+use crate::celoxis;
+
+/// Longest piece of an unexpected response body quoted in an error message.
+const MAX_DETAIL: usize = 300;
+
+/// A problem with one of the submitted time entries, as reported by Celoxis.
+#[derive(Debug, Clone)]
+pub struct EntryError {
+    /// Position of the entry in the submitted batch
+    pub index: Option<usize>,
+    pub field: Option<String>,
+    pub message: String,
+}
+
+#[derive(Debug)]
+pub enum CeloxisError {
+    /// The API key is missing, wrong or lacks permission (401/403)
+    Auth { status: StatusCode },
+    /// Celoxis refused the request body, with details per entry where it gives them
+    Validation {
+        message: String,
+        entries: Vec<EntryError>,
+    },
+    /// Too many requests (429), `retry_after` is in seconds
+    RateLimited { retry_after: Option<u64> },
+    /// Celoxis could not be reached at all
+    Network(reqwest::Error),
+    /// Any other error status, or a body that isn't what the API documents
+    UnexpectedResponse { status: StatusCode, detail: String },
+}
+
+impl CeloxisError {
+    /// Exit code of the CLI, so scripts can tell the failures apart.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            CeloxisError::Auth { .. } => 3,
+            CeloxisError::Validation { .. } => 4,
+            CeloxisError::RateLimited { .. } => 5,
+            CeloxisError::Network(_) => 6,
+            CeloxisError::UnexpectedResponse { .. } => 7,
+        }
+    }
+
+    /// Reads a JSON response, turning error statuses and malformed bodies into errors.
+    pub fn check<T: DeserializeOwned>(response: Response) -> Result<T, CeloxisError> {
+        let (status, body) = Self::check_status(response)?;
+        serde_json::from_str(&body).map_err(|e| CeloxisError::UnexpectedResponse {
+            status,
+            detail: format!("{} in {}", e, truncate(&body)),
+        })
+    }
+
+    /// Returns the body of a successful response and turns error statuses into errors.
+    pub fn check_status(response: Response) -> Result<(StatusCode, String), CeloxisError> {
+        let status = response.status();
+
+        if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN {
+            return Err(CeloxisError::Auth { status });
+        }
+        if status == StatusCode::TOO_MANY_REQUESTS {
+            let retry_after = response
+                .headers()
+                .get(RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(celoxis::parse_retry_after)
+                .map(|delay| delay.as_secs());
+            return Err(CeloxisError::RateLimited { retry_after });
+        }
+
+        let body = response.text()?;
+        if status == StatusCode::BAD_REQUEST || status == StatusCode::UNPROCESSABLE_ENTITY {
+            return Err(Self::validation(status, &body));
+        }
+        if !status.is_success() {
+            return Err(CeloxisError::UnexpectedResponse {
+                status,
+                detail: truncate(&body),
+            });
+        }
+        Ok((status, body))
+    }
+
+    /// Picks the message and per-entry errors out of an error body. The layout isn't
+    /// documented, so the usual field names are tried and the raw body is the fallback.
+    fn validation(status: StatusCode, body: &str) -> CeloxisError {
+        let Ok(json) = serde_json::from_str::<Value>(body) else {
+            return CeloxisError::Validation {
+                message: if body.trim().is_empty() {
+                    status.to_string()
+                } else {
+                    truncate(body)
+                },
+                entries: Vec::new(),
+            };
+        };
+
+        let text = |value: &Value, keys: &[&str]| {
+            keys.iter()
+                .find_map(|key| value.get(*key))
+                .map(|value| match value {
+                    Value::String(text) => text.clone(),
+                    other => other.to_string(),
+                })
+        };
+
+        let entries = ["errors", "details", "data"]
+            .iter()
+            .find_map(|key| json.get(*key).and_then(Value::as_array))
+            .map(|items| {
+                items
+                    .iter()
+                    .map(|item| EntryError {
+                        index: ["index", "row", "entry"]
+                            .iter()
+                            .find_map(|key| item.get(*key).and_then(Value::as_u64))
+                            .map(|index| index as usize),
+                        field: text(item, &["field", "property", "path"]),
+                        message: text(item, &["message", "error", "description"])
+                            .unwrap_or_else(|| item.to_string()),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        CeloxisError::Validation {
+            message: text(&json, &["message", "error", "description"])
+                .unwrap_or_else(|| status.to_string()),
+            entries,
+        }
+    }
+}
+
+fn truncate(body: &str) -> String {
+    let body = body.trim();
+    match body.char_indices().nth(MAX_DETAIL) {
+        Some((idx, _)) => format!("{}...", &body[..idx]),
+        None => body.to_string(),
+    }
+}
+
+impl fmt::Display for CeloxisError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CeloxisError::Auth { status } => write!(
+                f,
+                "Celoxis rejected the API key ({}), check it with `tw-upload auth show` \
+                 or save a new one with `tw-upload auth set`",
+                status
+            ),
+            CeloxisError::Validation { message, entries } => {
+                write!(f, "Celoxis rejected the request: {}", message)?;
+                for entry in entries {
+                    write!(f, "\n  ")?;
+                    if let Some(index) = entry.index {
+                        write!(f, "entry {}: ", index + 1)?;
+                    }
+                    if let Some(field) = &entry.field {
+                        write!(f, "{}: ", field)?;
+                    }
+                    write!(f, "{}", entry.message)?;
+                }
+                Ok(())
+            }
+            CeloxisError::RateLimited {
+                retry_after: Some(seconds),
+            } => write!(
+                f,
+                "Celoxis is rate limiting requests, try again in {} seconds",
+                seconds
+            ),
+            CeloxisError::RateLimited { retry_after: None } => {
+                write!(f, "Celoxis is rate limiting requests, try again later")
+            }
+            CeloxisError::Network(e) => write!(f, "Could not reach Celoxis: {}", e),
+            CeloxisError::UnexpectedResponse { status, detail } => {
+                write!(f, "Unexpected response from Celoxis ({}): {}", status, detail)
+            }
+        }
+    }
+}
+
+impl Error for CeloxisError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CeloxisError::Network(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for CeloxisError {
+    fn from(e: reqwest::Error) -> Self {
+        CeloxisError::Network(e)
+    }
+}
@@ -0,0 +1,206 @@
+use clap::ValueEnum;
+use std::error::Error;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::celoxis::CeloxisTimeEntry;
+
+/// How prepared time entries are written in dry-run mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExportFormat {
+    /// The JSON payload that would be POSTed to Celoxis
+    Json,
+    /// One row per time entry with a header line
+    Csv,
+    /// Aligned columns for reading in a terminal
+    Table,
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn render_csv(entries: &[CeloxisTimeEntry]) -> String {
+    let mut out = String::from("date,hours,timeCode,user,task,state,comments\n");
+    for entry in entries {
+        out.push_str(&format!(
+            "{},{:.2},{},{},{},{},{}\n",
+            entry.date,
+            entry.hours,
+            csv_field(&entry.time_code),
+            csv_field(&entry.user),
+            csv_field(&entry.task),
+            entry.state,
+            csv_field(&entry.comments)
+        ));
+    }
+    out
+}
+
+fn render_table(entries: &[CeloxisTimeEntry]) -> String {
+    let headers = ["Date", "Hours", "Task", "Time Code", "User", "Comments"];
+    let rows: Vec<[String; 6]> = entries
+        .iter()
+        .map(|entry| {
+            [
+                entry.date.clone(),
+                format!("{:.2}", entry.hours),
+                entry.task.clone(),
+                entry.time_code.clone(),
+                entry.user.clone(),
+                entry.comments.clone(),
+            ]
+        })
+        .collect();
+
+    let mut widths = headers.map(|header| header.chars().count());
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let format_row = |cells: &[&str]| -> String {
+        let line: Vec<String> = cells
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        format!("{}\n", line.join("  ").trim_end())
+    };
+
+    let mut out = format_row(&headers);
+    let separator: Vec<String> = widths.iter().map(|width| "-".repeat(*width)).collect();
+    out.push_str(&format!("{}\n", separator.join("  ")));
+    for row in &rows {
+        let cells: Vec<&str> = row.iter().map(String::as_str).collect();
+        out.push_str(&format_row(&cells));
+    }
+
+    let total: f64 = entries.iter().map(|entry| entry.hours).sum();
+    out.push_str(&format!(
+        "\nTotal: {:.2} hours in {} entries\n",
+        total,
+        entries.len()
+    ));
+    out
+}
+
+fn render(entries: &[CeloxisTimeEntry], format: ExportFormat) -> serde_json::Result<String> {
+    Ok(match format {
+        ExportFormat::Json => format!("{}\n", serde_json::to_string_pretty(entries)?),
+        ExportFormat::Csv => render_csv(entries),
+        ExportFormat::Table => render_table(entries),
+    })
+}
+
+/// Writes the entries in the given format to `output`, or to stdout when no path is given.
+pub fn write_entries(
+    entries: &[CeloxisTimeEntry],
+    format: ExportFormat,
+    output: Option<&Path>,
+) -> Result<(), Box<dyn Error>> {
+    let rendered = render(entries, format)?;
+
+    match output {
+        Some(path) => {
+            fs::write(path, rendered)?;
+            println!("Wrote {} time entries to {:?}", entries.len(), path);
+        }
+        None => io::stdout().write_all(rendered.as_bytes())?,
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries() -> Vec<CeloxisTimeEntry> {
+        vec![
+            CeloxisTimeEntry {
+                date: "2026-03-02".to_string(),
+                hours: 1.5,
+                time_code: "development".to_string(),
+                user: "jdoe".to_string(),
+                task: "4711".to_string(),
+                state: 0,
+                comments: "Fixed login, \"remember me\"".to_string(),
+            },
+            CeloxisTimeEntry {
+                date: "2026-03-03".to_string(),
+                hours: 0.25,
+                time_code: "meeting".to_string(),
+                user: "jdoe".to_string(),
+                task: "42".to_string(),
+                state: 0,
+                comments: "Café planning\nnext sprint".to_string(),
+            },
+        ]
+    }
+
+    #[test]
+    fn json_is_the_request_payload() {
+        let expected = r#"[
+  {
+    "date": "2026-03-02",
+    "hours": 1.5,
+    "timeCode": "development",
+    "user": "jdoe",
+    "task": "4711",
+    "state": 0,
+    "comments": "Fixed login, \"remember me\""
+  },
+  {
+    "date": "2026-03-03",
+    "hours": 0.25,
+    "timeCode": "meeting",
+    "user": "jdoe",
+    "task": "42",
+    "state": 0,
+    "comments": "Café planning\nnext sprint"
+  }
+]
+"#;
+        assert_eq!(render(&entries(), ExportFormat::Json).unwrap(), expected);
+    }
+
+    #[test]
+    fn csv_quotes_fields_with_separators() {
+        let expected = "date,hours,timeCode,user,task,state,comments
+2026-03-02,1.50,development,jdoe,4711,0,\"Fixed login, \"\"remember me\"\"\"
+2026-03-03,0.25,meeting,jdoe,42,0,\"Café planning
+next sprint\"
+";
+        assert_eq!(render(&entries(), ExportFormat::Csv).unwrap(), expected);
+    }
+
+    #[test]
+    fn table_aligns_columns_and_adds_the_total() {
+        let mut entries = entries();
+        entries[1].comments = "Café planning".to_string();
+        let expected = "\
+Date        Hours  Task  Time Code    User  Comments
+----------  -----  ----  -----------  ----  --------------------------
+2026-03-02  1.50   4711  development  jdoe  Fixed login, \"remember me\"
+2026-03-03  0.25   42    meeting      jdoe  Café planning
+
+Total: 1.75 hours in 2 entries
+";
+        assert_eq!(render(&entries, ExportFormat::Table).unwrap(), expected);
+    }
+
+    #[test]
+    fn empty_exports() {
+        assert_eq!(render(&[], ExportFormat::Json).unwrap(), "[]\n");
+        assert_eq!(
+            render(&[], ExportFormat::Csv).unwrap(),
+            "date,hours,timeCode,user,task,state,comments\n"
+        );
+    }
+}
@@ -0,0 +1,190 @@
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::{self, BufRead, IsTerminal};
+use std::path::PathBuf;
+
+use crate::timezone::DayZone;
+use crate::{DateRange, TimeEntry};
+
+const TIMEW_DATE_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+/// Config keys under this prefix are tw-upload's own settings, e.g. `reports.celoxis.time_code`.
+const REPORT_PREFIX: &str = "reports.celoxis.";
+
+#[derive(Debug, Deserialize)]
+struct ReportInterval {
+    id: u32,
+    start: String,
+    end: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+    annotation: Option<String>,
+}
+
+/// Input handed to a Timewarrior extension: a block of `key: value` config lines,
+/// a blank line, and a JSON array of the intervals matching the report's filter.
+#[derive(Debug)]
+pub struct ReportInput {
+    config: HashMap<String, String>,
+    entries: Vec<TimeEntry>,
+}
+
+/// tw-upload's own settings among the Timewarrior config, without their prefix.
+pub fn report_settings(config: &HashMap<String, String>) -> HashMap<String, String> {
+    config
+        .iter()
+        .filter(|(_, value)| !value.is_empty())
+        .filter_map(|(key, value)| {
+            let key = key.strip_prefix(REPORT_PREFIX)?;
+            Some((key.to_string(), value.clone()))
+        })
+        .collect()
+}
+
+/// Whether stdin carries a report. Timewarrior runs extensions without any arguments, so
+/// the report is recognised by its first line, a `key: value` config setting.
+pub fn stdin_has_report() -> bool {
+    let stdin = io::stdin();
+    if stdin.is_terminal() {
+        return false;
+    }
+    // Only looks at the buffered input, it is still there when the report is read
+    let mut stdin = stdin.lock();
+    stdin.fill_buf().is_ok_and(starts_with_setting)
+}
+
+fn starts_with_setting(input: &[u8]) -> bool {
+    let first_line = input
+        .split(|byte| *byte == b'\n')
+        .next()
+        .unwrap_or_default();
+    let Some((key, _)) = std::str::from_utf8(first_line)
+        .ok()
+        .and_then(|line| line.split_once(':'))
+    else {
+        return false;
+    };
+    !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+}
+
+fn parse_timestamp(value: &str) -> Result<DateTime<Utc>, Box<dyn Error>> {
+    let naive = NaiveDateTime::parse_from_str(value, TIMEW_DATE_FORMAT)
+        .map_err(|e| format!("Invalid timestamp '{}': {}", value, e))?;
+    Ok(DateTime::<Utc>::from_naive_utc_and_offset(naive, Utc))
+}
+
+impl ReportInput {
+    pub fn read(reader: impl BufRead) -> Result<Self, Box<dyn Error>> {
+        let mut config = HashMap::new();
+        let mut lines = reader.lines();
+
+        for line in lines.by_ref() {
+            let line = line?;
+            if line.trim().is_empty() {
+                break;
+            }
+            if let Some((key, value)) = line.split_once(':') {
+                config.insert(key.trim().to_string(), value.trim().to_string());
+            }
+        }
+
+        let body = lines.collect::<Result<Vec<_>, _>>()?.join("\n");
+        if body.trim().is_empty() {
+            return Err(
+                "No intervals received on stdin, is this running as a timew report?".into(),
+            );
+        }
+
+        let intervals: Vec<ReportInterval> = serde_json::from_str(&body)
+            .map_err(|e| format!("Could not parse intervals from timew: {}", e))?;
+
+        let mut entries = Vec::new();
+        for interval in intervals {
+            let end = interval.end.as_deref().map(parse_timestamp).transpose()?;
+            entries.push(TimeEntry::new(
+                format!("@{}", interval.id),
+                parse_timestamp(&interval.start)?,
+                end,
+                interval.tags,
+                interval.annotation,
+            ));
+        }
+        entries.sort_by_key(|entry| entry.start);
+
+        Ok(Self { config, entries })
+    }
+
+    /// Returns the `reports.celoxis.*` settings with the prefix stripped.
+    pub fn settings(&self) -> HashMap<String, String> {
+        report_settings(&self.config)
+    }
+
+    /// The Timewarrior database the report was generated from.
+    pub fn data_dir(&self) -> Option<PathBuf> {
+        self.config.get("temp.db").map(PathBuf::from)
+    }
+
+    /// The date range of the report, taken from the range Timewarrior resolved
+    /// (`temp.report.start`/`temp.report.end`) or, failing that, from the intervals.
+    pub fn date_range(&self, zone: &DayZone) -> Result<DateRange, Box<dyn Error>> {
+        let report_start = self
+            .config
+            .get("temp.report.start")
+            .filter(|value| !value.is_empty())
+            .map(|value| parse_timestamp(value))
+            .transpose()?;
+        let report_end = self
+            .config
+            .get("temp.report.end")
+            .filter(|value| !value.is_empty())
+            .map(|value| parse_timestamp(value))
+            .transpose()?;
+
+        let start = report_start
+            .or_else(|| self.entries.first().map(|entry| entry.start))
+            .unwrap_or_else(Utc::now);
+        // The report end is exclusive, so the last day is the one just before it
+        let end = report_end
+            .map(|end| end - Duration::seconds(1))
+            .or_else(|| {
+                self.entries
+                    .iter()
+                    .map(|entry| entry.end.unwrap_or_else(Utc::now))
+                    .max()
+            })
+            .unwrap_or(start)
+            .max(start);
+
+        Ok(DateRange {
+            start: zone.date_of(start),
+            end: zone.date_of(end),
+        })
+    }
+
+    pub fn into_entries(self) -> Vec<TimeEntry> {
+        self.entries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_start_with_a_setting() {
+        assert!(starts_with_setting(
+            b"debug: off\ntemp.db: /home/me/.timewarrior\n\n[]"
+        ));
+        assert!(starts_with_setting(b"reports.celoxis.time_code:\n\n[]"));
+        assert!(!starts_with_setting(
+            b"[{\"id\":1,\"start\":\"20260302T080000Z\"}]"
+        ));
+        assert!(!starts_with_setting(b"some text: here"));
+        assert!(!starts_with_setting(b""));
+    }
+}
@@ -0,0 +1,175 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
+
+use crate::TimeEntry;
+
+/// What makes intervals belong to the same group.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GroupStrategy {
+    /// The same set of tags
+    #[default]
+    Exact,
+    /// The same tags starting with the prefix, e.g. `project:acme`
+    Prefix,
+    /// The same hierarchical tag, e.g. `client.project.task`
+    Hierarchy,
+    /// The same annotation
+    Annotation,
+    /// The same text from the expression, e.g. `{project:}/{annotation}`
+    Expression,
+}
+
+/// What the intervals of a group have in common. Keys that aren't tags are kept apart
+/// from them, so an annotation `meeting` and a tag `meeting` make different groups.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GroupKey {
+    Tags(Vec<String>),
+    Annotation(String),
+    Expression(String),
+}
+
+impl fmt::Display for GroupKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GroupKey::Tags(tags) => write!(f, "Tags: {:?}", tags),
+            GroupKey::Annotation(text) => write!(f, "Annotation: {:?}", text),
+            GroupKey::Expression(text) => write!(f, "Expression: {:?}", text),
+        }
+    }
+}
+
+/// How intervals are grouped before they are assigned to tasks. Intervals the strategy
+/// has nothing to go by for, such as ones without an annotation, are grouped by their
+/// tags.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Grouping {
+    pub strategy: GroupStrategy,
+    /// Tag prefix used by the prefix strategy
+    pub prefix: String,
+    /// Separator between the levels of a hierarchical tag
+    pub separator: String,
+    /// Levels of a hierarchical tag to keep, all of them when not set
+    pub depth: Option<usize>,
+    /// Template of the expression strategy. `{annotation}` is the annotation, `{tags}`
+    /// all tags, and anything else in braces the value of the first tag starting with it
+    pub expression: Option<String>,
+    /// Tags stripped before grouping, e.g. `meeting`
+    pub ignore_tags: Vec<String>,
+}
+
+impl Default for Grouping {
+    fn default() -> Self {
+        Self {
+            strategy: GroupStrategy::Exact,
+            prefix: "project:".to_string(),
+            separator: ".".to_string(),
+            depth: None,
+            expression: None,
+            ignore_tags: Vec::new(),
+        }
+    }
+}
+
+impl Grouping {
+    /// Makes sure the selected strategy has what it needs.
+    pub fn check(&self) -> Result<(), Box<dyn Error>> {
+        match self.strategy {
+            GroupStrategy::Prefix if self.prefix.is_empty() => {
+                Err("Grouping by prefix needs grouping.prefix in config.toml".into())
+            }
+            GroupStrategy::Hierarchy if self.separator.is_empty() || self.depth == Some(0) => {
+                Err("Grouping by hierarchy needs a separator and a depth of at least 1".into())
+            }
+            GroupStrategy::Expression if self.expression.is_none() => {
+                Err("Grouping by expression needs grouping.expression in config.toml".into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// The entry's tags without the ignored ones, sorted.
+    pub fn tags(&self, entry: &TimeEntry) -> Vec<String> {
+        let mut tags: Vec<String> = entry
+            .tags
+            .iter()
+            .filter(|tag| !self.ignore_tags.contains(tag))
+            .cloned()
+            .collect();
+        tags.sort();
+        tags
+    }
+
+    /// The key of the group the entry belongs to.
+    pub fn key(&self, entry: &TimeEntry) -> GroupKey {
+        let tags = self.tags(entry);
+        let key = match self.strategy {
+            GroupStrategy::Exact => None,
+            GroupStrategy::Prefix => {
+                let matching: Vec<String> = tags
+                    .iter()
+                    .filter(|tag| tag.starts_with(&self.prefix))
+                    .cloned()
+                    .collect();
+                Some(matching)
+                    .filter(|matching| !matching.is_empty())
+                    .map(GroupKey::Tags)
+            }
+            GroupStrategy::Hierarchy => {
+                tags.iter()
+                    .find(|tag| tag.contains(&self.separator))
+                    .map(|tag| {
+                        let levels: Vec<&str> = tag.split(&self.separator).collect();
+                        let depth = self.depth.unwrap_or(levels.len()).min(levels.len());
+                        GroupKey::Tags(vec![levels[..depth].join(&self.separator)])
+                    })
+            }
+            GroupStrategy::Annotation => entry
+                .annotation
+                .as_ref()
+                .map(|annotation| annotation.trim().to_string())
+                .filter(|annotation| !annotation.is_empty())
+                .map(GroupKey::Annotation),
+            GroupStrategy::Expression => self
+                .expression
+                .as_deref()
+                .and_then(|expression| render(expression, &tags, entry.annotation.as_deref()))
+                .map(GroupKey::Expression),
+        };
+        key.unwrap_or(GroupKey::Tags(tags))
+    }
+}
+
+/// Fills in the placeholders of the expression. Gives nothing when none of them has a
+/// value, as the text would be the same for every interval.
+fn render(expression: &str, tags: &[String], annotation: Option<&str>) -> Option<String> {
+    let mut text = String::new();
+    let mut found = false;
+    let mut rest = expression;
+
+    while let Some(open) = rest.find('{') {
+        let Some(close) = rest[open..].find('}').map(|close| open + close) else {
+            break;
+        };
+        text.push_str(&rest[..open]);
+        let value = match &rest[open + 1..close] {
+            "annotation" => annotation.map(str::trim).unwrap_or_default().to_string(),
+            "tags" => tags.join(" "),
+            prefix => tags
+                .iter()
+                .find_map(|tag| tag.strip_prefix(prefix))
+                .unwrap_or_default()
+                .to_string(),
+        };
+        found |= !value.is_empty();
+        text.push_str(&value);
+        rest = &rest[close + 1..];
+    }
+    text.push_str(rest);
+
+    Some(text.trim().to_string()).filter(|_| found)
+}
@@ -0,0 +1,211 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::TimeEntry;
+
+const LEDGER_FILE: &str = "celoxis_submissions.json";
+
+/// One interval that has been submitted to Celoxis.
+///
+/// Intervals are identified by their start, end and tags rather than the
+/// `file-line` id, which changes whenever Timewarrior rewrites a data file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LedgerRecord {
+    pub start: DateTime<Utc>,
+    pub end: Option<DateTime<Utc>>,
+    pub tags: Vec<String>,
+    pub project: String,
+    pub task: String,
+    pub submitted_at: DateTime<Utc>,
+    pub celoxis_id: Option<String>,
+    /// The day submitted, for an interval that crosses midnight and is booked per day
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub day: Option<NaiveDate>,
+    /// Celoxis accepted the request without saying whether this entry was saved. The
+    /// interval isn't submitted again until the record is removed.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub unconfirmed: bool,
+}
+
+impl LedgerRecord {
+    fn key(&self) -> String {
+        interval_key(self.start, self.end, &self.tags, self.day)
+    }
+}
+
+#[derive(Debug)]
+pub struct SubmissionLedger {
+    path: PathBuf,
+    records: HashMap<String, LedgerRecord>,
+}
+
+fn interval_key(
+    start: DateTime<Utc>,
+    end: Option<DateTime<Utc>>,
+    tags: &[String],
+    day: Option<NaiveDate>,
+) -> String {
+    let mut tags = tags.to_vec();
+    tags.sort();
+    let end = end.map_or_else(|| "open".to_string(), |end| end.to_rfc3339());
+    let key = format!("{}|{}|{}", start.to_rfc3339(), end, tags.join("\u{1f}"));
+    match day {
+        Some(day) => format!("{}|{}", key, day),
+        None => key,
+    }
+}
+
+/// The day an entry is recorded under, only parts of an interval crossing midnight have one.
+fn entry_day(entry: &TimeEntry) -> Option<NaiveDate> {
+    entry
+        .part
+        .as_ref()
+        .filter(|part| part.days.len() > 1)
+        .map(|_| entry.day)
+}
+
+/// Keys the entry may have been recorded under: the day part or the whole interval, and,
+/// once the interval is closed, also as it was while still being tracked.
+fn entry_keys(entry: &TimeEntry, day: Option<NaiveDate>) -> Vec<String> {
+    let mut keys = vec![interval_key(
+        entry.start,
+        entry.tracked_end(),
+        &entry.tags,
+        day,
+    )];
+    if day.is_some() {
+        keys.push(interval_key(
+            entry.start,
+            entry.tracked_end(),
+            &entry.tags,
+            None,
+        ));
+    }
+    if entry.tracked_end().is_some() {
+        keys.push(interval_key(entry.start, None, &entry.tags, day));
+        if day.is_some() {
+            keys.push(interval_key(entry.start, None, &entry.tags, None));
+        }
+    }
+    keys
+}
+
+impl SubmissionLedger {
+    /// Loads the ledger stored in the Timewarrior data directory, or starts an empty one.
+    pub fn load(data_dir: &Path) -> Result<Self, Box<dyn Error>> {
+        let path = data_dir.join(LEDGER_FILE);
+        let mut records = HashMap::new();
+
+        if path.exists() {
+            let content = fs::read_to_string(&path)?;
+            let stored: Vec<LedgerRecord> = serde_json::from_str(&content)
+                .map_err(|e| format!("Could not read submission ledger {:?}: {}", path, e))?;
+            for record in stored {
+                records.insert(record.key(), record);
+            }
+        }
+
+        Ok(Self { path, records })
+    }
+
+    pub fn save(&self) -> Result<(), Box<dyn Error>> {
+        let mut records: Vec<&LedgerRecord> = self.records.values().collect();
+        records.sort_by_key(|record| record.start);
+
+        // Write to a temporary file first so an interrupted run cannot corrupt the ledger
+        let tmp_path = self.path.with_extension("json.tmp");
+        fs::write(&tmp_path, serde_json::to_string_pretty(&records)?)?;
+        fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+
+    /// The record of the entry, or of the whole interval if it was submitted in one piece.
+    pub fn get(&self, entry: &TimeEntry) -> Option<&LedgerRecord> {
+        entry_keys(entry, entry_day(entry))
+            .iter()
+            .find_map(|key| self.records.get(key))
+    }
+
+    /// Whether the entry is in the ledger, confirmed or not.
+    pub fn contains(&self, entry: &TimeEntry) -> bool {
+        self.get(entry).is_some()
+    }
+
+    /// Whether the entry may have been submitted without Celoxis confirming it.
+    pub fn is_unconfirmed(&self, entry: &TimeEntry) -> bool {
+        self.get(entry).is_some_and(|record| record.unconfirmed)
+    }
+
+    /// Whether every day of the entry's interval has been confirmed by Celoxis.
+    pub fn is_complete(&self, entry: &TimeEntry) -> bool {
+        let confirmed = |day| {
+            entry_keys(entry, day).iter().any(|key| {
+                self.records
+                    .get(key)
+                    .is_some_and(|record| !record.unconfirmed)
+            })
+        };
+        match &entry.part {
+            Some(part) => part.days.iter().all(|day| confirmed(Some(*day))),
+            None => confirmed(None),
+        }
+    }
+
+    /// Marks every entry Celoxis confirmed as submitted.
+    pub fn apply(&self, entries: &mut [TimeEntry]) {
+        for entry in entries {
+            if let Some(record) = self.get(entry).filter(|record| !record.unconfirmed) {
+                entry.submitted = true;
+                entry.celoxis_id = record.celoxis_id.clone();
+            }
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn record(
+        &mut self,
+        entry: &TimeEntry,
+        project: &str,
+        task: &str,
+        celoxis_id: Option<String>,
+    ) {
+        self.insert(entry, project, task, celoxis_id, false);
+    }
+
+    /// Records an entry Celoxis may or may not have saved, so it isn't booked twice.
+    pub fn record_unconfirmed(&mut self, entry: &TimeEntry, project: &str, task: &str) {
+        self.insert(entry, project, task, None, true);
+    }
+
+    fn insert(
+        &mut self,
+        entry: &TimeEntry,
+        project: &str,
+        task: &str,
+        celoxis_id: Option<String>,
+        unconfirmed: bool,
+    ) {
+        let mut tags = entry.tags.clone();
+        tags.sort();
+
+        let record = LedgerRecord {
+            start: entry.start,
+            end: entry.tracked_end(),
+            tags,
+            project: project.to_string(),
+            task: task.to_string(),
+            submitted_at: Utc::now(),
+            celoxis_id,
+            day: entry_day(entry),
+            unconfirmed,
+        };
+        self.records.insert(record.key(), record);
+    }
+}
@@ -1,17 +1,51 @@
-use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use chrono::{DateTime, Local, Months, NaiveDate, Utc};
+use clap::Parser;
 use directories::BaseDirs;
 use inquire::list_option::ListOption;
 use inquire::validator::Validation;
 use inquire::DateSelect;
 use inquire::{Confirm, MultiSelect, Select, Text};
+use std::cell::Cell;
 use std::collections::HashMap;
 use std::error::Error;
 use std::fs;
 use std::path::{Path, PathBuf};
 use regex::Regex;
 
+// Declared first so the status! macro can be used in the other modules
+#[macro_use]
+mod status;
+
+mod auth;
 mod celoxis;
-use celoxis::{CeloxisApi, CeloxisProject, CeloxisTask, CeloxisTimeEntry};
+mod cli;
+mod commands;
+mod config;
+mod draft;
+mod error;
+mod export;
+mod extension;
+mod grouping;
+mod ledger;
+mod parser;
+mod review;
+mod rules;
+mod timew;
+mod timezone;
+mod validate;
+use celoxis::{
+    CeloxisApi, CeloxisProject, CeloxisTask, CeloxisTimeEntry, EntryOutcome, UserPreferences,
+};
+use cli::{Cli, Command, GlobalArgs};
+use config::{Config, Rounding, RoundingLevel};
+use draft::{Draft, DraftFile};
+use grouping::GroupKey;
+use error::CeloxisError;
+use extension::ReportInput;
+use timew::OpenIntervals;
+use ledger::SubmissionLedger;
+use rules::MappingRules;
+use timezone::DayZone;
 
 #[derive(Debug, Clone)]
 struct TimeEntry {
@@ -22,86 +56,145 @@ struct TimeEntry {
     annotation: Option<String>,
     submitted: bool,
     celoxis_id: Option<String>,
+    /// Still being tracked in Timewarrior, `end` is only set when it was capped at now
+    open: bool,
+    /// Local day of the interval, or of this part of it
+    day: NaiveDate,
+    /// Set on the parts of an interval that crosses midnight
+    part: Option<IntervalPart>,
+}
+
+/// The piece of an interval on one day. The interval itself stays the identity used for
+/// the ledger and for tagging in Timewarrior.
+#[derive(Debug, Clone)]
+struct IntervalPart {
+    start: DateTime<Utc>,
+    /// None for the last part of an interval that is still open
+    end: Option<DateTime<Utc>>,
+    /// Every day the whole interval touches
+    days: Vec<NaiveDate>,
 }
 
 impl TimeEntry {
     fn from_timewarrior(line: &str, entry_id: String) -> Result<Self, Box<dyn Error>> {
-        if line.trim().is_empty() {
-            return Err("Empty line".into());
-        }
+        let interval = parser::parse_interval(line)?;
+
+        Ok(TimeEntry::new(
+            entry_id,
+            interval.start,
+            interval.end,
+            interval.tags,
+            interval.annotation,
+        ))
+    }
 
-        if !line.starts_with("inc ") {
-            return Err("Line doesn't start with 'inc'".into());
-        }
+    fn new(
+        id: String,
+        start: DateTime<Utc>,
+        end: Option<DateTime<Utc>>,
+        tags: Vec<String>,
+        annotation: Option<String>,
+    ) -> Self {
+        // Tags written back after a submission are state, not part of the interval's identity
+        let (celoxis_tags, tags): (Vec<String>, Vec<String>) = tags
+            .into_iter()
+            .partition(|tag| tag.starts_with(timew::TAG_PREFIX));
+        let celoxis_id = celoxis_tags
+            .iter()
+            .find(|tag| tag.as_str() != timew::SUBMITTED_TAG)
+            .map(|tag| tag.trim_start_matches(timew::TAG_PREFIX).to_string());
 
-        let parts: Vec<&str> = line.splitn(2, "inc ").collect();
-        if parts.len() != 2 {
-            return Err("Invalid interval format".into());
+        TimeEntry {
+            id,
+            start,
+            end,
+            tags,
+            annotation,
+            submitted: !celoxis_tags.is_empty(),
+            celoxis_id,
+            open: end.is_none(),
+            // Until split_days() picks the day in the configured timezone
+            day: start.with_timezone(&Local).date_naive(),
+            part: None,
         }
+    }
 
-        let interval_and_tags: Vec<&str> = parts[1].splitn(2, '#').collect();
-        let interval = interval_and_tags[0].trim();
+    /// Splits the interval at midnight into one part per day. An interval within a
+    /// single day is returned as it is.
+    fn split_days(mut self, zone: &DayZone) -> Vec<TimeEntry> {
+        let end = self.end.unwrap_or_else(Utc::now);
+        let mut bounds = Vec::new();
+        let mut day = zone.date_of(self.start);
+        let mut start = self.start;
+        loop {
+            let Some(next_day) = day.succ_opt() else {
+                bounds.push((day, start, self.end));
+                break;
+            };
+            let midnight = zone.start_of_day(next_day);
+            if midnight >= end {
+                bounds.push((day, start, self.end));
+                break;
+            }
+            bounds.push((day, start, Some(midnight)));
+            start = midnight;
+            day = next_day;
+        }
 
-        let timestamps: Vec<&str> = interval.split(" - ").collect();
-        if timestamps.len() != 2 {
-            return Err("Invalid timestamp format".into());
+        if bounds.len() == 1 {
+            self.day = bounds[0].0;
+            return vec![self];
         }
 
-        let start_str = timestamps[0].trim();
-        let start = chrono::NaiveDateTime::parse_from_str(start_str, "%Y%m%dT%H%M%SZ")?;
-        let start = DateTime::<Utc>::from_naive_utc_and_offset(start, Utc);
+        let days: Vec<NaiveDate> = bounds.iter().map(|(day, _, _)| *day).collect();
+        bounds
+            .into_iter()
+            .map(|(day, start, end)| TimeEntry {
+                day,
+                part: Some(IntervalPart {
+                    start,
+                    end,
+                    days: days.clone(),
+                }),
+                ..self.clone()
+            })
+            .collect()
+    }
 
-        let end = if timestamps[1].trim().is_empty() {
+    /// The end as Timewarrior has it, None while the interval is still being tracked.
+    fn tracked_end(&self) -> Option<DateTime<Utc>> {
+        if self.open {
             None
         } else {
-            let end_str = timestamps[1].trim();
-            let end = chrono::NaiveDateTime::parse_from_str(end_str, "%Y%m%dT%H%M%SZ")?;
-            Some(DateTime::<Utc>::from_naive_utc_and_offset(end, Utc))
-        };
+            self.end
+        }
+    }
 
-        let tags = if interval_and_tags.len() > 1 {
-            let tag_str = interval_and_tags[1].trim();
-            let mut tags = Vec::new();
-            let mut current_tag = String::new();
-            let mut in_quotes = false;
-
-            for c in tag_str.chars() {
-                match c {
-                    '"' => {
-                        in_quotes = !in_quotes;
-                        if !in_quotes && !current_tag.is_empty() {
-                            tags.push(current_tag.clone());
-                            current_tag.clear();
-                        }
-                    }
-                    ' ' if !in_quotes => {
-                        if !current_tag.is_empty() {
-                            tags.push(current_tag.clone());
-                            current_tag.clear();
-                        }
-                    }
-                    _ => current_tag.push(c),
-                }
-            }
+    /// Gives an open interval an end, including its last part if it was split.
+    fn close_at(&mut self, end: DateTime<Utc>) {
+        self.end = Some(end);
+        if let Some(part) = &mut self.part {
+            part.end.get_or_insert(end);
+        }
+    }
 
-            if !current_tag.is_empty() {
-                tags.push(current_tag);
-            }
+    /// Start and end of the time on `day`, the whole interval unless it was split.
+    fn span(&self) -> (DateTime<Utc>, Option<DateTime<Utc>>) {
+        match &self.part {
+            Some(part) => (part.start, part.end),
+            None => (self.start, self.end),
+        }
+    }
 
-            tags
-        } else {
-            Vec::new()
-        };
+    /// Tracked time, up to now for an interval that is still open.
+    fn duration(&self) -> chrono::Duration {
+        let (start, end) = self.span();
+        end.unwrap_or_else(Utc::now) - start
+    }
 
-        Ok(TimeEntry {
-            id: entry_id,
-            start,
-            end,
-            tags,
-            annotation: None,
-            submitted: false,
-            celoxis_id: None,
-        })
+    /// Tracked time rounded to whole minutes.
+    fn minutes(&self) -> i64 {
+        (self.duration().num_seconds() + 30) / 60
     }
 }
 
@@ -113,6 +206,8 @@ struct DateRange {
 
 #[derive(Debug, Clone)]
 struct GroupedEntry {
+    key: GroupKey,
+    /// Tags of all the group's intervals, without the ignored ones
     tags: Vec<String>,
     total_duration: HashMap<NaiveDate, i64>, // Duration in minutes per day
     entries: HashMap<NaiveDate, Vec<TimeEntry>>,
@@ -128,12 +223,19 @@ struct TaskAssignment {
     summary: String,
     time_code: String,
     user: String,
+    rounding: Rounding,
+    /// Minutes entered by hand for a day, submitted instead of the rounded tracked time
+    adjusted: HashMap<NaiveDate, i64>,
 }
 
 #[derive(Debug)]
 struct TimeData {
     entries: Vec<TimeEntry>,
     data_dir: PathBuf,
+    ledger: SubmissionLedger,
+    report_settings: HashMap<String, String>, // reports.celoxis.* keys from the Timewarrior config
+    config: Config,
+    zone: DayZone,
 }
 
 struct CeloxisData {
@@ -144,10 +246,18 @@ struct CeloxisData {
 }
 
 impl CeloxisData {
-    fn new() -> Result<Self, Box<dyn Error>> {
-        let mut api = CeloxisApi::new()?;
-        // Load projects immediately
-        let projects = api.get_projects(true)?;
+    fn new(global: &GlobalArgs) -> Result<Self, Box<dyn Error>> {
+        let mut api = CeloxisApi::new(config::api_config(global)?)?;
+        // Load projects immediately, from the cache unless it has expired
+        let projects = api.get_projects(false)?;
+        if let Some(updated) = api.projects_updated() {
+            status!(
+                "Using {} projects fetched {}{}",
+                projects.len(),
+                celoxis::format_age(updated),
+                if api.is_offline() { " (offline)" } else { "" }
+            );
+        }
 
         Ok(Self {
             api,
@@ -157,11 +267,11 @@ impl CeloxisData {
         })
     }
 
-    fn select_project(&mut self) -> Result<(), Box<dyn Error>> {
+    fn select_project(&mut self, suggested: Option<&str>) -> Result<(), Box<dyn Error>> {
         let projects = if let Some(ref projects) = self.cached_projects {
             projects.clone()
         } else {
-            let projects = self.api.get_projects(true)?;
+            let projects = self.api.get_projects(false)?;
             self.cached_projects = Some(projects.clone());
             projects
         };
@@ -171,10 +281,15 @@ impl CeloxisData {
             .map(|p| format!("{} - {}", p.id, p.name))
             .collect();
 
+        let cursor = suggested
+            .and_then(|id| projects.iter().position(|p| p.id == id))
+            .unwrap_or(0);
+
         if let Some(selection) = Select::new(
             "Select project to associate time entries with:",
             project_options.clone(),
         )
+        .with_starting_cursor(cursor)
         .prompt_skippable()?
         {
             let idx = project_options
@@ -187,14 +302,24 @@ impl CeloxisData {
         Ok(())
     }
 
-    fn select_tasks(&mut self) -> Result<(), Box<dyn Error>> {
+    fn select_tasks(&mut self, suggested: Option<&str>) -> Result<(), Box<dyn Error>> {
         if let Some(project) = &self.selected_project {
-            let force_refresh = if self.api.get_cached_tasks(&project.id).is_some() {
-                Confirm::new("Refresh task list from Celoxis?")
+            // Expired task lists are fetched again anyway, so only ask about fresh ones
+            let updated = self.api.tasks_updated(&project.id);
+            let force_refresh = match updated {
+                Some(updated) if !self.api.is_stale(Some(updated)) && !self.api.is_offline() => {
+                    Confirm::new(&format!(
+                        "Task list was fetched {}, refresh it from Celoxis?",
+                        celoxis::format_age(updated)
+                    ))
                     .with_default(false)
                     .prompt()?
-            } else {
-                true
+                }
+                Some(updated) if self.api.is_offline() => {
+                    status!("Using tasks cached {} (offline)", celoxis::format_age(updated));
+                    false
+                }
+                _ => false,
             };
 
             let tasks = self.api.get_tasks(&project.id, force_refresh)?;
@@ -204,11 +329,16 @@ impl CeloxisData {
                 .map(|t| format!("{} - {}", t.id, t.name))
                 .collect();
 
+            let cursor = suggested
+                .and_then(|id| tasks.iter().position(|t| t.id == id))
+                .unwrap_or(0);
+
             // Changed from MultiSelect to Select
             if let Ok(selection) = Select::new(
                 "Select task to associate time entries with:",
                 task_options.clone(),
             )
+            .with_starting_cursor(cursor)
             .prompt()
             {
                 let idx = task_options.iter().position(|x| x == &selection).unwrap();
@@ -218,73 +348,362 @@ impl CeloxisData {
 
         Ok(())
     }
+
+    /// Points at the cache age when looking something up in cached data failed.
+    fn cache_hint(&self, updated: Option<DateTime<Utc>>) -> String {
+        match updated {
+            Some(updated) if self.api.is_offline() => format!(
+                " (offline, cache from {}, it may be outdated)",
+                celoxis::format_age(updated)
+            ),
+            _ => String::new(),
+        }
+    }
+
+    fn find_project(&mut self, project_id: &str) -> Result<CeloxisProject, Box<dyn Error>> {
+        if self.cached_projects.is_none() {
+            self.cached_projects = Some(self.api.get_projects(false)?);
+        }
+
+        let find = |projects: &Option<Vec<CeloxisProject>>| {
+            projects.iter().flatten().find(|p| p.id == project_id).cloned()
+        };
+        if let Some(project) = find(&self.cached_projects) {
+            return Ok(project);
+        }
+
+        // The cached project list may be outdated, try once more against Celoxis
+        if !self.api.is_offline() {
+            self.cached_projects = Some(self.api.get_projects(true)?);
+            if let Some(project) = find(&self.cached_projects) {
+                return Ok(project);
+            }
+        }
+
+        Err(format!(
+            "No active Celoxis project with id '{}'{}",
+            project_id,
+            self.cache_hint(self.api.projects_updated())
+        )
+        .into())
+    }
+
+    fn find_task(
+        &mut self,
+        project_id: &str,
+        task_id: &str,
+    ) -> Result<CeloxisTask, Box<dyn Error>> {
+        let cached = self.api.get_cached_tasks(project_id).is_some();
+        let tasks = self.api.get_tasks(project_id, false)?;
+        if let Some(task) = tasks.iter().find(|t| t.id == task_id) {
+            return Ok(task.clone());
+        }
+
+        // The cached task list may be outdated, try once more against Celoxis
+        if cached && !self.api.is_offline() {
+            let tasks = self.api.get_tasks(project_id, true)?;
+            if let Some(task) = tasks.into_iter().find(|t| t.id == task_id) {
+                return Ok(task);
+            }
+        }
+
+        Err(format!(
+            "No task with id '{}' in project '{}'{}",
+            task_id,
+            project_id,
+            self.cache_hint(self.api.tasks_updated(project_id))
+        )
+        .into())
+    }
 }
 
 impl TimeData {
-    fn new(date_range: &DateRange) -> Result<Self, Box<dyn Error>> {
-        let data_dir = Self::detect_timewarrior_dir()?;
-        let entries = Self::read_time_entries(&data_dir, date_range)?;
+    fn new(
+        date_range: &DateRange,
+        config: Config,
+        data_dir: Option<&Path>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let zone = DayZone::new(config.timezone);
+        let data_dir = Self::detect_timewarrior_dir(data_dir, config.data_dir.as_deref())?;
+        let report_settings = match Self::timewarrior_cfg(&data_dir) {
+            Some(path) => extension::report_settings(&timew::read_config(&path)?),
+            None => HashMap::new(),
+        };
+        let mut entries = Self::read_time_entries(&data_dir, date_range, &zone)?;
+
+        let ledger = SubmissionLedger::load(&data_dir)?;
+        ledger.apply(&mut entries);
+
+        Ok(TimeData {
+            entries,
+            data_dir,
+            ledger,
+            report_settings,
+            config,
+            zone,
+        })
+    }
+
+    /// Builds the time data from a Timewarrior extension report instead of the data files.
+    fn from_report(
+        input: ReportInput,
+        config: Config,
+        data_dir: Option<&Path>,
+    ) -> Result<(Self, DateRange), Box<dyn Error>> {
+        let zone = DayZone::new(config.timezone);
+        let date_range = input.date_range(&zone)?;
+        // Timewarrior tells the extension which database it used
+        let data_dir = match data_dir.map(Path::to_path_buf).or_else(|| input.data_dir()) {
+            Some(dir) => dir,
+            None => Self::detect_timewarrior_dir(None, config.data_dir.as_deref())?,
+        };
+        let report_settings = input.settings();
+        let mut entries: Vec<TimeEntry> = input
+            .into_entries()
+            .into_iter()
+            .flat_map(|entry| entry.split_days(&zone))
+            .collect();
 
-        Ok(TimeData { entries, data_dir })
+        let ledger = SubmissionLedger::load(&data_dir)?;
+        ledger.apply(&mut entries);
+
+        let time_data = TimeData {
+            entries,
+            data_dir,
+            ledger,
+            report_settings,
+            config,
+            zone,
+        };
+        Ok((time_data, date_range))
     }
-    fn is_file_in_date_range(filename: &str, range: &DateRange) -> bool {
-        // Expected format: YYYY-MM.data
-        let re = Regex::new(r"^(\d{4})-(\d{2})\.data$").unwrap();
 
-        if let Some(captures) = re.captures(filename) {
-            if let (Some(year_str), Some(month_str)) = (captures.get(1), captures.get(2)) {
-                if let (Ok(year), Ok(month)) = (year_str.as_str().parse::<i32>(), month_str.as_str().parse::<u32>()) {
-                    let file_date = NaiveDate::from_ymd_opt(year, month, 1).unwrap_or(range.start);
-                    let next_month = if month == 12 {
-                        NaiveDate::from_ymd_opt(year + 1, 1, 1)
-                    } else {
-                        NaiveDate::from_ymd_opt(year, month + 1, 1)
-                    }.unwrap_or(range.end);
+    /// Reads the intervals either from the Timewarrior data files or, in report mode,
+    /// from stdin. In report mode the range defaults to the one Timewarrior resolved.
+    fn load(
+        range: Option<DateRange>,
+        global: &GlobalArgs,
+    ) -> Result<(Self, DateRange), Box<dyn Error>> {
+        let mut config = Config::load()?;
+        if let Some(strategy) = global.group_by {
+            config.grouping.strategy = strategy;
+        }
+        config.grouping.check()?;
+        if global.report {
+            let input = ReportInput::read(std::io::stdin().lock())?;
+            let (time_data, report_range) =
+                Self::from_report(input, config, global.data_dir.as_deref())?;
+            return Ok((time_data, range.unwrap_or(report_range)));
+        }
+
+        let range = match range {
+            Some(range) => range,
+            None => Self::prompt_date_range()?,
+        };
+        Ok((Self::new(&range, config, global.data_dir.as_deref())?, range))
+    }
 
-                    // Check if the file's month overlaps with our date range
-                    return !(next_month <= range.start || file_date > range.end);
+    /// Applies the open interval policy to intervals in the range that are still being
+    /// tracked, so the time submitted for them doesn't keep moving.
+    fn settle_open_intervals(
+        &mut self,
+        range: &DateRange,
+        global: &GlobalArgs,
+    ) -> Result<(), Box<dyn Error>> {
+        let in_range = |entry: &TimeEntry| {
+            entry.open && entry.end.is_none() && entry.day >= range.start && entry.day <= range.end
+        };
+        let Some(open) = self.entries.iter().find(|entry| in_range(entry)).cloned() else {
+            return Ok(());
+        };
+        let started = self.zone.format(open.start, "%Y-%m-%d %H:%M");
+
+        match global.open_intervals.unwrap_or(self.config.open_intervals) {
+            OpenIntervals::Block => status!(
+                "Note: {:?} started {} is still being tracked, submitting it will be refused \
+                 unless it is stopped or --open-intervals is exclude, cap or stop",
+                open.tags, started
+            ),
+            OpenIntervals::Exclude => {
+                status!(
+                    "Leaving out {:?} started {}, it is still being tracked",
+                    open.tags, started
+                );
+                self.entries.retain(|entry| !in_range(entry));
+            }
+            OpenIntervals::Cap => {
+                let now = Utc::now();
+                status!(
+                    "Counting {:?} started {} up to {}, time tracked after that is not submitted",
+                    open.tags,
+                    started,
+                    self.zone.format(now, "%H:%M")
+                );
+                for entry in self.entries.iter_mut().filter(|entry| in_range(entry)) {
+                    entry.close_at(now);
+                }
+            }
+            OpenIntervals::Stop => {
+                let end = timew::stop(&self.data_dir, open.start)?;
+                status!(
+                    "Stopped tracking {:?} at {}",
+                    open.tags,
+                    self.zone.format(end, "%H:%M")
+                );
+                for entry in self.entries.iter_mut().filter(|entry| entry.start == open.start) {
+                    entry.close_at(end);
+                    entry.open = false;
                 }
             }
         }
-        false
+        Ok(())
     }
 
-    fn detect_timewarrior_dir() -> Result<PathBuf, Box<dyn Error>> {
-        println!("Detecting TimeWarrior directory...");
+    /// Warns about overlaps, gaps and unusually long intervals or days. With `trim`
+    /// overlaps are trimmed right away, otherwise the user is asked whether to trim them.
+    fn check_intervals(&mut self, trim: Option<bool>) -> Result<(), Box<dyn Error>> {
+        let findings = validate::check(&self.entries, &self.config.validation, &self.zone);
+        findings.print();
+        if findings.overlaps == 0 {
+            return Ok(());
+        }
 
-        if let Some(base_dirs) = BaseDirs::new() {
-            let xdg_data = base_dirs.data_dir().join("timewarrior");
-            println!("Checking XDG path: {:?}", xdg_data);
-            if xdg_data.exists() {
-                return Ok(xdg_data);
-            }
+        let trim = match trim {
+            Some(trim) => trim,
+            None => Confirm::new("Trim overlapping intervals so their time is only counted once?")
+                .with_default(true)
+                .prompt()?,
+        };
+        if trim {
+            let (trimmed, dropped) = validate::trim_overlaps(&mut self.entries);
+            status!(
+                "Trimmed {} intervals and left out {} that lie within another one",
+                trimmed, dropped
+            );
         }
+        Ok(())
+    }
 
-        let legacy_dir = dirs::home_dir()
-            .ok_or("Could not determine home directory")?
-            .join(".timewarrior");
+    /// Resolves the Celoxis user and time code, preferring `reports.celoxis.username` and
+    /// `reports.celoxis.time_code` from the Timewarrior config over the saved preferences.
+    fn user_prefs(&self, api: &mut CeloxisApi) -> Result<UserPreferences, Box<dyn Error>> {
+        let username = self.report_settings.get("username").cloned();
+        let time_code = self.report_settings.get("time_code").cloned();
+
+        if let (Some(username), Some(time_code)) = (username.clone(), time_code.clone()) {
+            return Ok(UserPreferences {
+                username,
+                time_code,
+                rounding: api.rounding(),
+            });
+        }
 
-        println!("Checking legacy path: {:?}", legacy_dir);
-        if legacy_dir.exists() {
-            return Ok(legacy_dir);
+        let mut prefs = api.ensure_user_prefs()?;
+        if let Some(username) = username {
+            prefs.username = username;
+        }
+        if let Some(time_code) = time_code {
+            prefs.time_code = time_code;
+        }
+        Ok(prefs)
+    }
+    /// The month a Timewarrior data file holds, for files named like `2024-03.data`.
+    /// The other files next to them, such as `tags.data`, `undo.data` and `backlog.data`,
+    /// don't hold intervals.
+    fn data_file_month(filename: &str) -> Option<NaiveDate> {
+        let re = Regex::new(r"^(\d{4})-(\d{2})\.data$").unwrap();
+        let captures = re.captures(filename)?;
+        let year = captures[1].parse().ok()?;
+        let month = captures[2].parse().ok()?;
+        NaiveDate::from_ymd_opt(year, month, 1)
+    }
+
+    /// Whether a data file can hold intervals on days in the range. The files are split
+    /// by UTC month while the days are in the configured timezone, and an interval is
+    /// stored with the month it starts in, so a day either side of the range counts too.
+    fn is_month_in_date_range(month: NaiveDate, range: &DateRange) -> bool {
+        let next_month = month
+            .checked_add_months(Months::new(1))
+            .unwrap_or(NaiveDate::MAX);
+        let start = range.start.pred_opt().unwrap_or(range.start);
+        let end = range.end.succ_opt().unwrap_or(range.end);
+
+        // Check if the file's month overlaps with our date range
+        !(next_month <= start || month > end)
+    }
+
+    /// Finds the Timewarrior database the way Timewarrior does. A directory given with
+    /// --data-dir, in TIMEWARRIORDB or in config.toml must exist, otherwise
+    /// `~/.timewarrior` is used if it exists and the XDG data directory after that.
+    fn detect_timewarrior_dir(
+        data_dir: Option<&Path>,
+        configured: Option<&Path>,
+    ) -> Result<PathBuf, Box<dyn Error>> {
+        let from_env = std::env::var_os("TIMEWARRIORDB")
+            .filter(|value| !value.is_empty())
+            .map(PathBuf::from);
+        let explicit = [
+            (data_dir.map(Path::to_path_buf), "--data-dir"),
+            (from_env, "TIMEWARRIORDB"),
+            (configured.map(Path::to_path_buf), "data_dir in config.toml"),
+        ];
+        if let Some((dir, source)) = explicit
+            .into_iter()
+            .find_map(|(dir, source)| Some((dir?, source)))
+        {
+            if !dir.is_dir() {
+                return Err(
+                    format!("Timewarrior database {:?} from {} does not exist", dir, source).into(),
+                );
+            }
+            status!("Using TimeWarrior directory {:?} from {}", dir, source);
+            return Ok(dir);
         }
 
+        status!("Detecting TimeWarrior directory...");
+        let mut candidates = Vec::new();
+        if let Some(home) = dirs::home_dir() {
+            candidates.push(home.join(".timewarrior"));
+        }
         if let Some(base_dirs) = BaseDirs::new() {
-            let xdg_data = base_dirs.data_dir().join("timewarrior");
-            fs::create_dir_all(&xdg_data)?;
-            println!("Creating XDG directory: {:?}", xdg_data);
-            Ok(xdg_data)
-        } else {
-            Err("Could not determine TimeWarrior data directory".into())
+            candidates.push(base_dirs.data_dir().join("timewarrior"));
         }
+
+        for dir in &candidates {
+            status!("Checking path: {:?}", dir);
+            if dir.is_dir() {
+                return Ok(dir.clone());
+            }
+        }
+
+        Err(format!(
+            "No TimeWarrior database found in {:?}, set TIMEWARRIORDB or pass --data-dir",
+            candidates
+        )
+        .into())
+    }
+
+    /// The `timewarrior.cfg` belonging to a database: inside it, or where the XDG layout
+    /// keeps it in `~/.config/timewarrior`.
+    fn timewarrior_cfg(data_dir: &Path) -> Option<PathBuf> {
+        let xdg_config = dirs::config_dir().map(|dir| dir.join("timewarrior"));
+        [Some(data_dir.to_path_buf()), xdg_config]
+            .into_iter()
+            .flatten()
+            .map(|dir| dir.join("timewarrior.cfg"))
+            .find(|path| path.is_file())
     }
 
-    fn read_time_entries(data_dir: &Path, date_range: &DateRange) -> Result<Vec<TimeEntry>, Box<dyn Error>> {
+    fn read_time_entries(
+        data_dir: &Path,
+        date_range: &DateRange,
+        zone: &DayZone,
+    ) -> Result<Vec<TimeEntry>, Box<dyn Error>> {
         let data_path = data_dir.join("data");
-        println!("Looking for data in: {:?}", data_path);
+        status!("Looking for data in: {:?}", data_path);
 
         if !data_path.exists() {
-            println!("Data directory does not exist");
+            status!("Data directory does not exist");
             return Ok(Vec::new());
         }
 
@@ -301,19 +720,19 @@ impl TimeData {
                 continue;
             }
 
-            // Skip special files
             if let Some(filename) = path.file_name().and_then(|s| s.to_str()) {
-                if filename == "tags.data" || filename == "undo.data" || filename == "backlog.data" {
+                // Only the monthly files hold intervals
+                let Some(month) = Self::data_file_month(filename) else {
                     continue;
-                }
+                };
 
                 // Check if file is within date range before processing
-                if !Self::is_file_in_date_range(filename, date_range) {
-                    println!("Skipping file outside date range: {}", filename);
+                if !Self::is_month_in_date_range(month, date_range) {
+                    status!("Skipping file outside date range: {}", filename);
                     continue;
                 }
 
-                println!("Processing file in range: {}", filename);
+                status!("Processing file in range: {}", filename);
                 let content = fs::read_to_string(&path)?;
 
                 for (line_num, line) in content.lines().enumerate() {
@@ -331,80 +750,106 @@ impl TimeData {
 
                     match TimeEntry::from_timewarrior(line, entry_id.clone()) {
                         Ok(entry) => {
-                            // Additional date range check for individual entries
-                            let entry_date = Self::to_local_date(entry.start);
-                            if entry_date >= date_range.start && entry_date <= date_range.end {
-                                entries.push(entry);
+                            // Additional date range check for each day of the interval
+                            for part in entry.split_days(zone) {
+                                if part.day >= date_range.start && part.day <= date_range.end {
+                                    entries.push(part);
+                                }
                             }
                         }
                         Err(e) => {
-                            println!("Error parsing line {} in {}: {}", line_num + 1, filename, e);
+                            status!("Error parsing line {} in {}: {}", line_num + 1, filename, e);
                         }
                     }
                 }
             }
         }
 
-        entries.sort_by(|a, b| a.start.cmp(&b.start));
-        println!("Found {} entries within date range", entries.len());
+        entries.sort_by_key(|entry| entry.span().0);
+        status!("Found {} entries within date range", entries.len());
 
         Ok(entries)
     }
 
-    fn to_local_date(utc: DateTime<Utc>) -> NaiveDate {
-        utc.with_timezone(&Local).naive_local().date()
-    }
-
     fn filter_by_date_range(&self, range: &DateRange) -> Vec<&TimeEntry> {
         self.entries
             .iter()
-            .filter(|entry| {
-                let entry_date = Self::to_local_date(entry.start);
-                entry_date >= range.start && entry_date <= range.end
-            })
+            .filter(|entry| entry.day >= range.start && entry.day <= range.end)
             .collect()
     }
 
-    fn group_entries_by_tags(&self, entries: Vec<&TimeEntry>) -> Vec<GroupedEntry> {
-        let mut groups: HashMap<Vec<String>, HashMap<NaiveDate, Vec<&TimeEntry>>> = HashMap::new();
+    /// Entries in the date range that have not been submitted to Celoxis yet.
+    fn pending_entries(&self, range: &DateRange) -> Vec<&TimeEntry> {
+        let (submitted, pending): (Vec<&TimeEntry>, Vec<&TimeEntry>) = self
+            .filter_by_date_range(range)
+            .into_iter()
+            .partition(|entry| entry.submitted);
 
-        for entry in entries {
-            let sorted_tags = {
-                let mut tags = entry.tags.clone();
-                tags.sort();
-                tags
-            };
+        if !submitted.is_empty() {
+            status!(
+                "Skipping {} intervals that were already submitted",
+                submitted.len()
+            );
+        }
+        let (unconfirmed, pending): (Vec<&TimeEntry>, Vec<&TimeEntry>) = pending
+            .into_iter()
+            .partition(|entry| self.ledger.is_unconfirmed(entry));
+        if !unconfirmed.is_empty() {
+            status!(
+                "Skipping {} intervals submitted without confirmation from Celoxis, check your \
+                 timesheet there and remove their records from {:?} if they are missing",
+                unconfirmed.len(),
+                self.ledger.path()
+            );
+        }
+        pending
+    }
 
-            let entry_date = Self::to_local_date(entry.start);
+    /// Whether the entry is one pending_entries() keeps.
+    fn is_pending(&self, entry: &TimeEntry) -> bool {
+        !entry.submitted && !self.ledger.is_unconfirmed(entry)
+    }
 
+    /// Groups the entries as configured in `[grouping]`, adding up their time per day.
+    fn group_entries_by_tags(&self, entries: Vec<&TimeEntry>) -> Vec<GroupedEntry> {
+        let mut groups: HashMap<GroupKey, HashMap<NaiveDate, Vec<&TimeEntry>>> = HashMap::new();
+
+        for entry in entries {
             groups
-                .entry(sorted_tags)
-                .or_insert_with(HashMap::new)
-                .entry(entry_date)
-                .or_insert_with(Vec::new)
+                .entry(self.config.grouping.key(entry))
+                .or_default()
+                .entry(entry.day)
+                .or_default()
                 .push(entry);
         }
 
-        groups
+        let mut grouped: Vec<GroupedEntry> = groups
             .into_iter()
-            .map(|(tags, date_entries_map)| {
+            .map(|(key, date_entries_map)| {
+                let mut tags: Vec<String> = date_entries_map
+                    .values()
+                    .flatten()
+                    .flat_map(|entry| self.config.grouping.tags(entry))
+                    .collect();
+                tags.sort();
+                tags.dedup();
+
                 let mut total_duration = HashMap::new();
                 let mut entries = HashMap::new();
 
                 for (date, entries_vec) in date_entries_map.iter() {
-                    let duration = entries_vec
+                    // Add up seconds so the minutes aren't cut off for every interval
+                    let seconds: i64 = entries_vec
                         .iter()
-                        .map(|entry| {
-                            let end = entry.end.unwrap_or_else(|| Utc::now());
-                            (end - entry.start).num_minutes()
-                        })
+                        .map(|entry| entry.duration().num_seconds())
                         .sum();
 
-                    total_duration.insert(*date, duration);
+                    total_duration.insert(*date, (seconds + 30) / 60);
                     entries.insert(*date, entries_vec.iter().map(|&e| e.clone()).collect());
                 }
 
                 GroupedEntry {
+                    key,
                     tags,
                     total_duration,
                     entries,
@@ -413,7 +858,11 @@ impl TimeData {
                         .all(|entries| entries.iter().all(|e| e.submitted)),
                 }
             })
-            .collect()
+            .collect();
+
+        // Keep group numbers stable between runs so they can be referenced from the CLI
+        grouped.sort_by(|a, b| a.key.cmp(&b.key));
+        grouped
     }
 
     fn prompt_date_range() -> Result<DateRange, Box<dyn Error>> {
@@ -431,11 +880,19 @@ impl TimeData {
 
     fn display_grouped_entries(grouped_entries: &[GroupedEntry]) {
         for (idx, group) in grouped_entries.iter().enumerate() {
-            println!("\nGroup {}", idx + 1);
+            if group.all_submitted {
+                status!("\nGroup {} [Submitted]", idx + 1);
+            } else {
+                status!("\nGroup {}", idx + 1);
+            }
 
-            // Extract description and project from tags if available
+            // Extract description and project from the tags the group is made of
+            let key_tags: &[String] = match &group.key {
+                GroupKey::Tags(tags) => tags,
+                _ => &[],
+            };
             let (description, project) =
-                group.tags.iter().fold((None, None), |(desc, proj), tag| {
+                key_tags.iter().fold((None, None), |(desc, proj), tag| {
                     if tag.starts_with("description:") {
                         (Some(tag.trim_start_matches("description:")), proj)
                     } else if tag.starts_with("project:") {
@@ -448,16 +905,16 @@ impl TimeData {
             // Display tags based on available information
             match (description, project) {
                 (Some(desc), Some(proj)) => {
-                    println!("Description: {} (Project: {})", desc.trim(), proj.trim())
+                    status!("Description: {} (Project: {})", desc.trim(), proj.trim())
                 }
-                (Some(desc), None) => println!("Description: {}", desc.trim()),
-                (None, Some(proj)) => println!("Project: {}", proj.trim()),
-                (None, None) => println!("Tags: {:?}", group.tags),
+                (Some(desc), None) => status!("Description: {}", desc.trim()),
+                (None, Some(proj)) => status!("Project: {}", proj.trim()),
+                (None, None) => status!("{}", group.key),
             }
 
-            // println!("Duration by date:");
+            // status!("Duration by date:");
             for (date, duration) in &group.total_duration {
-                println!(
+                status!(
                     "  {} - {} hours {} minutes",
                     date,
                     duration / 60,
@@ -465,7 +922,7 @@ impl TimeData {
                 );
             }
 
-            // println!(
+            // status!(
             //     "Submission Status: {}",
             //     if group.all_submitted {
             //         "All Submitted"
@@ -474,16 +931,16 @@ impl TimeData {
             //     }
             // );
 
-            // println!("Individual Entries by Date:");
+            // status!("Individual Entries by Date:");
             // for (date, entries) in &group.entries {
-            //     println!("  Date: {}", date);
+            //     status!("  Date: {}", date);
             //     for entry in entries {
             //         let duration = entry.end.map_or_else(
             //             || "Ongoing".to_string(),
             //             |end| format!("{} minutes", (end - entry.start).num_minutes()),
             //         );
             //         let local_time = entry.start.with_timezone(&Local);
-            //         println!(
+            //         status!(
             //             "    - {} ({}) [{}]",
             //             local_time.format("%H:%M"),
             //             duration,
@@ -502,7 +959,7 @@ impl TimeData {
         grouped_entries: &[GroupedEntry],
     ) -> Result<Vec<&GroupedEntry>, Box<dyn Error>> {
         if grouped_entries.is_empty() {
-            println!("No grouped entries found.");
+            status!("No grouped entries found.");
             return Ok(Vec::new());
         }
 
@@ -512,9 +969,13 @@ impl TimeData {
             .map(|(idx, group)| {
                 let total_hours: f64 = group.total_duration.values().sum::<i64>() as f64 / 60.0;
 
-                // Extract description and project from tags
+                // Extract description and project from the tags the group is made of
+                let key_tags: &[String] = match &group.key {
+                    GroupKey::Tags(tags) => tags,
+                    _ => &[],
+                };
                 let (description, project) =
-                    group.tags.iter().fold((None, None), |(desc, proj), tag| {
+                    key_tags.iter().fold((None, None), |(desc, proj), tag| {
                         if tag.starts_with("description:") {
                             (Some(tag.trim_start_matches("description:")), proj)
                         } else if tag.starts_with("project:") {
@@ -531,7 +992,7 @@ impl TimeData {
                     }
                     (Some(desc), None) => desc.trim().to_string(),
                     (None, Some(proj)) => format!("Project: {}", proj.trim()),
-                    (None, None) => format!("Tags: {:?}", group.tags),
+                    (None, None) => group.key.to_string(),
                 };
 
                 format!(
@@ -584,63 +1045,303 @@ impl TimeData {
             .flat_map(|group| group.total_duration.values())
             .sum();
 
-        println!("\nGrouping {} sets of entries", groups.len());
-        println!(
+        status!("\nGrouping {} sets of entries", groups.len());
+        status!(
             "Total combined duration: {:.2} hours",
             total_minutes as f64 / 60.0
         );
 
-        println!("Including entries with these tags:");
+        status!("Including these groups:");
         for group in &groups {
-            println!("  - {:?}", group.tags);
+            status!("  - {}", group.key);
         }
 
         Ok(groups.into_iter().cloned().collect())
     }
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    // First select date range
-    let date_range = TimeData::prompt_date_range()?;
+/// Celoxis failures get their own exit codes, see `CeloxisError::exit_code`.
+fn exit_code(error: &(dyn Error + 'static)) -> i32 {
+    match error.downcast_ref::<CeloxisError>() {
+        Some(e) => e.exit_code(),
+        None => 1,
+    }
+}
+
+fn main() {
+    let mut cli = Cli::parse();
+    if !cli.global.report && std::env::args_os().len() == 1 && extension::stdin_has_report() {
+        cli.global.report = true;
+    }
+
+    // Only the wizard and submit export, other commands print to stdout as usual
+    let exports = matches!(
+        cli.command,
+        None | Some(Command::Resume) | Some(Command::Submit(_))
+    );
+    if exports && cli.global.dry_run && cli.global.output.is_none() {
+        status::use_stderr();
+    }
+
+    if let Err(e) = run(cli) {
+        eprintln!("Error: {}", e);
+        std::process::exit(exit_code(e.as_ref()));
+    }
+}
+
+fn run(cli: Cli) -> Result<(), Box<dyn Error>> {
+    match cli.command {
+        None => run_wizard(&cli.global, false),
+        Some(Command::Resume) => run_wizard(&cli.global, true),
+        Some(Command::List(range)) => commands::list(&range, &cli.global),
+        Some(Command::Group {
+            range,
+            intervals,
+            all,
+        }) => commands::group(&range, intervals, all, &cli.global),
+        Some(Command::Submit(args)) => commands::submit(&args, &cli.global),
+        Some(Command::Projects { refresh }) => commands::projects(refresh, &cli.global),
+        Some(Command::Tasks { project, refresh }) => {
+            commands::tasks(&project, refresh, &cli.global)
+        }
+        Some(Command::Cache { action }) => commands::cache(&action, &cli.global),
+        Some(Command::Auth { action }) => commands::auth(&action, &cli.global),
+        Some(Command::Config { action }) => commands::config(&action),
+    }
+}
 
-    // Create TimeData with date range
-    let time_data = TimeData::new(&date_range)?;
-    println!("Found {} time entries in selected date range", time_data.entries.len());
+/// Runs the interactive wizard. With `resume` the session saved in the draft is continued.
+fn run_wizard(global: &GlobalArgs, resume: bool) -> Result<(), Box<dyn Error>> {
+    let draft_file = DraftFile::new()?;
+    let draft = if resume {
+        let draft = draft_file.load()?.ok_or("There is no saved session to resume")?;
+        status!(
+            "Resuming the session saved {} for {} to {}",
+            draft.saved_at.with_timezone(&Local).format("%Y-%m-%d %H:%M"),
+            draft.start,
+            draft.end
+        );
+        Some(draft)
+    } else {
+        if let Ok(Some(draft)) = draft_file.load() {
+            status!(
+                "Note: a session saved {} can be continued with `tw-upload resume`, \
+                 assigning anything now replaces it",
+                draft.saved_at.with_timezone(&Local).format("%Y-%m-%d %H:%M")
+            );
+        }
+        None
+    };
 
-    let mut celoxis = CeloxisData::new()?;
+    // First select date range, unless Timewarrior already resolved one for us
+    let range = draft.as_ref().map(Draft::date_range);
+    let (mut time_data, date_range) = TimeData::load(range, global)?;
+    status!("Found {} time entries in selected date range", time_data.entries.len());
+    time_data.settle_open_intervals(&date_range, global)?;
+    time_data.check_intervals(None)?;
+
+    let mut celoxis = CeloxisData::new(global)?;
 
     // Get user preferences once at start
-    let user_prefs = celoxis.api.ensure_user_prefs()?;
+    let user_prefs = time_data.user_prefs(&mut celoxis.api)?;
 
-    // Filter entries by date range
-    let filtered_entries = time_data.filter_by_date_range(&date_range);
-    println!("Found {} entries in date range", filtered_entries.len());
+    // Filter entries by date range, leaving out anything already submitted
+    let filtered_entries = time_data.pending_entries(&date_range);
+    status!("Found {} entries in date range", filtered_entries.len());
 
     // Group entries
     let mut grouped_entries = time_data.group_entries_by_tags(filtered_entries);
-    println!("Grouped into {} sets", grouped_entries.len());
+    status!("Grouped into {} sets", grouped_entries.len());
+
+    // Every change to the assignments is saved so the session can be resumed. A draft left
+    // by an earlier session is kept until this one has assigned something.
+    let assigned = Cell::new(false);
+    let autosave = |assignments: &[TaskAssignment]| {
+        if assignments.is_empty() && !assigned.get() {
+            return;
+        }
+        assigned.set(true);
+        if let Err(e) = draft_file.save(&date_range, assignments) {
+            status!("Warning: could not save the session draft: {}", e);
+        }
+    };
+
+    // Pick up the assignments of a resumed session first
+    let mut assignments = match &draft {
+        Some(draft) => restore_draft(&mut celoxis, &mut grouped_entries, draft, &user_prefs),
+        None => Vec::new(),
+    };
+
+    // Assign whatever the mapping rules cover, the rest is assigned by hand below
+    let rules_path = global
+        .rules
+        .clone()
+        .or_else(|| time_data.config.rules_file.clone())
+        .unwrap_or_else(|| MappingRules::default_path(&time_data.data_dir));
+    let rules = MappingRules::load(&rules_path, &time_data.config.rules)?;
+    let targets = grouped_entries
+        .iter()
+        .map(|group| {
+            let rule = rules.resolve(&group.tags)?;
+            Some(ProposedTarget {
+                project_id: rule.project_id.clone(),
+                task_id: rule.task_id.clone(),
+                time_code: rule.time_code.clone(),
+                summary: rule.render_summary(&group.tags),
+            })
+        })
+        .collect();
+    assignments.extend(preassign_groups(
+        &mut celoxis,
+        &mut grouped_entries,
+        targets,
+        &user_prefs,
+        "mapping rules",
+    )?);
+    autosave(&assignments);
+
+    // Then offer the tasks these tag sets were submitted to last time
+    let targets = grouped_entries
+        .iter()
+        .map(|group| {
+            let record = celoxis.api.suggest_assignment(&group.tags)?;
+            Some(ProposedTarget {
+                project_id: record.project_id.clone(),
+                task_id: record.task_id.clone(),
+                time_code: None,
+                summary: None,
+            })
+        })
+        .collect();
+    assignments.extend(preassign_groups(
+        &mut celoxis,
+        &mut grouped_entries,
+        targets,
+        &user_prefs,
+        "previous submissions",
+    )?);
+    autosave(&assignments);
+
+    // Keep processing until all entries are assigned or user is done
+    assign_groups(
+        &mut celoxis,
+        &mut grouped_entries,
+        &user_prefs,
+        &mut assignments,
+        &autosave,
+    )?;
+
+    // If we have assignments, let the user review them before they are submitted
+    if !assignments.is_empty() {
+        let confirm_submit = review::review_assignments(
+            &mut celoxis,
+            &mut assignments,
+            &mut grouped_entries,
+            &user_prefs,
+            &autosave,
+            global.dry_run,
+        )?;
+
+        if confirm_submit {
+            let mut pending = assignments;
+            while !pending.is_empty() {
+                let result = submit_assignments(
+                    &mut celoxis.api,
+                    &mut time_data.ledger,
+                    &time_data.data_dir,
+                    &pending,
+                    global,
+                );
+                let failed = match result {
+                    Ok(failed) => failed,
+                    Err(e) => {
+                        status!("Error submitting entries");
+                        return Err(e);
+                    }
+                };
+                if failed.is_empty() {
+                    if !global.dry_run {
+                        draft_file.remove()?;
+                    }
+                    break;
+                }
+                autosave(&failed);
+
+                // Only what Celoxis didn't save is offered again
+                let retry = "Retry the failed entries";
+                let reassign = "Assign the failed entries to another task";
+                let choice = Select::new(
+                    "Some entries were not saved, what now?",
+                    vec![retry, reassign, "Leave them for later"],
+                )
+                .prompt()?;
+                pending = if choice == retry {
+                    failed
+                } else if choice == reassign {
+                    let mut groups = Vec::new();
+                    return_groups(&mut groups, failed.into_iter().flat_map(|a| a.groups).collect());
+                    let mut reassigned = Vec::new();
+                    assign_groups(
+                        &mut celoxis,
+                        &mut groups,
+                        &user_prefs,
+                        &mut reassigned,
+                        &autosave,
+                    )?;
+                    reassigned
+                } else {
+                    status!("The failed entries are kept, continue with `tw-upload resume`");
+                    Vec::new()
+                };
+            }
+        } else {
+            status!("Submission cancelled. Continue later with `tw-upload resume`");
+        }
+    }
 
-    let mut assignments: Vec<TaskAssignment> = Vec::new();
+    Ok(())
+}
 
+/// Lets the user pick groups and the Celoxis task to book them to until every group is
+/// assigned or the user is done. New assignments are added to `assignments`, which is
+/// autosaved after each one. Groups left unassigned stay in `groups`.
+fn assign_groups(
+    celoxis: &mut CeloxisData,
+    groups: &mut Vec<GroupedEntry>,
+    user_prefs: &UserPreferences,
+    assignments: &mut Vec<TaskAssignment>,
+    autosave: &dyn Fn(&[TaskAssignment]),
+) -> Result<(), Box<dyn Error>> {
     // Keep processing until all entries are assigned or user is done
-    while !grouped_entries.is_empty() {
-        TimeData::display_grouped_entries(&grouped_entries);
+    while !groups.is_empty() {
+        TimeData::display_grouped_entries(groups);
 
-        let selected_groups = TimeData::select_multiple_groups(&grouped_entries)?;
+        let selected_groups = TimeData::select_multiple_groups(groups)?;
         if selected_groups.is_empty() {
-            println!("No groups selected. Done assigning.");
+            status!("No groups selected. Done assigning.");
             break;
         }
 
         let processed_groups = TimeData::process_selected_groups(selected_groups.clone())?;
 
+        // Pre-select the task these tags were last submitted to
+        let suggestion = processed_groups.iter().find_map(|group| {
+            let record = celoxis.api.suggest_assignment(&group.tags)?;
+            Some((record.project_id.clone(), record.task_id.clone()))
+        });
+        let (suggested_project, suggested_task) = match &suggestion {
+            Some((project_id, task_id)) => (Some(project_id.as_str()), Some(task_id.as_str())),
+            None => (None, None),
+        };
+
         // Now select project and tasks for these specific entries
-        celoxis.select_project()?;
+        celoxis.select_project(suggested_project)?;
         if let Some(project) = celoxis.selected_project.clone() {
-            celoxis.select_tasks()?;
+            let suggested_task = suggested_task.filter(|_| suggested_project == Some(&project.id));
+            celoxis.select_tasks(suggested_task)?;
 
             if celoxis.selected_tasks.is_empty() {
-                println!("No tasks selected. Skipping these entries.");
+                status!("No tasks selected. Skipping these entries.");
                 continue;
             }
 
@@ -662,10 +1363,16 @@ fn main() -> Result<(), Box<dyn Error>> {
                 celoxis.selected_tasks[idx].clone()
             };
 
-            // Get summary for the entries
-            let summary = Text::new("Enter work summary for these entries:")
-                .with_validator(|input: &str| {
-                    if input.trim().is_empty() {
+            // Get summary for the entries, annotations can stand in for it
+            let has_annotations = processed_groups.iter().any(GroupedEntry::has_annotations);
+            let prompt = if has_annotations {
+                "Enter work summary for these entries (empty to use the annotations):"
+            } else {
+                "Enter work summary for these entries:"
+            };
+            let summary = Text::new(prompt)
+                .with_validator(move |input: &str| {
+                    if input.trim().is_empty() && !has_annotations {
                         Ok(Validation::Invalid("Summary cannot be empty".into()))
                     } else {
                         Ok(Validation::Valid)
@@ -673,34 +1380,24 @@ fn main() -> Result<(), Box<dyn Error>> {
                 })
                 .prompt()?;
 
-            // Calculate total duration by date
-            let mut total_duration = HashMap::new();
-            for group in &processed_groups {
-                for (date, duration) in &group.total_duration {
-                    *total_duration.entry(*date).or_insert(0) += duration;
-                }
-            }
-
-            // Create the assignment
-            let assignment = TaskAssignment {
-                groups: processed_groups.clone(),
-                total_duration,
-                celoxis_project: project,
-                celoxis_task: task,
+            let assignment = TaskAssignment::new(
+                processed_groups,
+                project,
+                task,
                 summary,
-                time_code: user_prefs.time_code.clone(),
-                user: user_prefs.username.clone(),
-            };
+                user_prefs,
+            );
             assignments.push(assignment);
+            autosave(assignments);
 
             // Collect the tags we need to remove
-            let tags_to_remove: Vec<_> = selected_groups.iter().map(|g| g.tags.clone()).collect();
+            let keys_to_remove: Vec<_> = selected_groups.iter().map(|g| g.key.clone()).collect();
 
             // Remove the processed groups
-            grouped_entries.retain(|group| !tags_to_remove.contains(&group.tags));
+            groups.retain(|group| !keys_to_remove.contains(&group.key));
         }
 
-        if !grouped_entries.is_empty() {
+        if !groups.is_empty() {
             let continue_processing = Confirm::new("Assign more entries to tasks?")
                 .with_default(true)
                 .prompt()?;
@@ -711,73 +1408,718 @@ fn main() -> Result<(), Box<dyn Error>> {
         }
     }
 
-    // If we have assignments, confirm and process them
+
+    Ok(())
+}
+
+/// Rebuilds the assignments of a saved session against the current Timewarrior data.
+/// Groups without pending intervals are dropped, e.g. because they were submitted in the
+/// meantime, and groups whose task no longer exists go back to `groups`.
+fn restore_draft(
+    celoxis: &mut CeloxisData,
+    groups: &mut Vec<GroupedEntry>,
+    draft: &Draft,
+    user_prefs: &UserPreferences,
+) -> Vec<TaskAssignment> {
+    let mut assignments = Vec::new();
+
+    for saved in &draft.assignments {
+        let mut restored = Vec::new();
+        for saved_group in &saved.groups {
+            // Only the days saved with this assignment, the rest may belong to another one
+            let key = saved_group.group_key();
+            let group = groups
+                .iter()
+                .position(|g| g.key == key)
+                .and_then(|pos| {
+                    if saved_group.dates.is_empty() {
+                        return Some(groups.remove(pos));
+                    }
+                    let part = groups[pos].split_off(&saved_group.dates);
+                    if groups[pos].is_empty() {
+                        groups.remove(pos);
+                    }
+                    part
+                });
+            let Some(group) = group else {
+                status!("  {} has no pending intervals any more", key);
+                continue;
+            };
+            let minutes: i64 = group.total_duration.values().sum();
+            if minutes != saved_group.minutes {
+                status!(
+                    "  {} changed since the draft was saved: {:.2} -> {:.2} hours",
+                    group.key,
+                    saved_group.minutes as f64 / 60.0,
+                    minutes as f64 / 60.0
+                );
+            }
+            restored.push(group);
+        }
+        if restored.is_empty() {
+            continue;
+        }
+
+        let found = celoxis.find_project(&saved.project_id).and_then(|project| {
+            Ok((project, celoxis.find_task(&saved.project_id, &saved.task_id)?))
+        });
+        match found {
+            Ok((project, task)) => {
+                let mut assignment =
+                    TaskAssignment::new(restored, project, task, saved.summary.clone(), user_prefs);
+                assignment.time_code = saved.time_code.clone();
+                assignment.adjusted = saved
+                    .adjusted
+                    .iter()
+                    .filter(|(date, _)| assignment.total_duration.contains_key(date))
+                    .map(|(date, minutes)| (*date, *minutes))
+                    .collect();
+                assignments.push(assignment);
+            }
+            Err(e) => {
+                status!("  {}, these groups need to be assigned again", e);
+                return_groups(groups, restored);
+            }
+        }
+    }
+
+    status!("\nRestored {} assignments from the draft:", assignments.len());
+    for assignment in &assignments {
+        assignment.display();
+    }
+    assignments
+}
+
+/// Where a group should be booked, as proposed by a mapping rule or by the history
+/// of earlier submissions.
+#[derive(Debug, Clone, PartialEq)]
+struct ProposedTarget {
+    project_id: String,
+    task_id: String,
+    time_code: Option<String>,
+    summary: Option<String>,
+}
+
+/// Builds assignments for the groups that have a proposed target and asks once whether
+/// to accept them all. Groups with the same target end up in one assignment; groups
+/// without a target, or whose proposal is declined, stay in `groups`.
+fn preassign_groups(
+    celoxis: &mut CeloxisData,
+    groups: &mut Vec<GroupedEntry>,
+    targets: Vec<Option<ProposedTarget>>,
+    user_prefs: &UserPreferences,
+    source: &str,
+) -> Result<Vec<TaskAssignment>, Box<dyn Error>> {
+    if targets.iter().all(Option::is_none) {
+        return Ok(Vec::new());
+    }
+
+    let mut matched: Vec<(ProposedTarget, Vec<GroupedEntry>)> = Vec::new();
+    let mut unmatched = Vec::new();
+
+    for (group, target) in groups.drain(..).zip(targets) {
+        let Some(target) = target else {
+            unmatched.push(group);
+            continue;
+        };
+
+        match matched.iter_mut().find(|(existing, _)| *existing == target) {
+            Some((_, target_groups)) => target_groups.push(group),
+            None => matched.push((target, vec![group])),
+        }
+    }
+
+    let mut assignments = Vec::new();
+    for (target, target_groups) in matched {
+        let task = celoxis.find_project(&target.project_id).and_then(|project| {
+            Ok((project, celoxis.find_task(&target.project_id, &target.task_id)?))
+        });
+        let (project, task) = match task {
+            Ok(found) => found,
+            Err(e) => {
+                status!("Skipping assignment from {}: {}", source, e);
+                unmatched.extend(target_groups);
+                continue;
+            }
+        };
+
+        let summary = target.summary.unwrap_or_default();
+        let mut assignment =
+            TaskAssignment::new(target_groups, project, task, summary, user_prefs);
+        if let Some(time_code) = target.time_code {
+            assignment.time_code = time_code;
+        }
+        assignments.push(assignment);
+    }
+
     if !assignments.is_empty() {
-        println!("\nReady to process {} task assignments", assignments.len());
-        println!("\nAssignments to be processed:");
+        let group_count: usize = assignments.iter().map(|a| a.groups.len()).sum();
+        status!("\nAssigned {} groups from {}:", group_count, source);
         for assignment in &assignments {
-            println!(
-                "\nProject: {} (ID: {})",
-                assignment.celoxis_project.name, assignment.celoxis_project.id
+            assignment.display();
+        }
+
+        let accept = Confirm::new(&format!("Accept all assignments from {}?", source))
+            .with_default(true)
+            .prompt()?;
+        if !accept {
+            unmatched.extend(assignments.drain(..).flat_map(|a| a.groups));
+        }
+    }
+
+    // Only ask for summaries once the assignments are accepted
+    for assignment in &mut assignments {
+        if assignment.summary.trim().is_empty()
+            && !assignment.groups.iter().any(GroupedEntry::has_annotations)
+        {
+            assignment.summary =
+                Text::new(&format!("Enter work summary for {}:", assignment.celoxis_task.name))
+                    .with_validator(|input: &str| {
+                        if input.trim().is_empty() {
+                            Ok(Validation::Invalid("Summary cannot be empty".into()))
+                        } else {
+                            Ok(Validation::Valid)
+                        }
+                    })
+                    .prompt()?;
+        }
+    }
+
+    unmatched.sort_by(|a, b| a.key.cmp(&b.key));
+    *groups = unmatched;
+    Ok(assignments)
+}
+
+/// Converts every assignment into Celoxis time entries and submits them in one request.
+///
+/// Refuses to submit if any of the intervals is already in the submission ledger, even
+/// unconfirmed. The intervals of every entry Celoxis saved are recorded in the ledger with
+/// the Celoxis id
+/// and tagged in Timewarrior, and each group's tags are remembered with the task for
+/// future suggestions. Returns the parts of the assignments Celoxis did not save, so they
+/// can be retried or assigned elsewhere.
+///
+/// In dry-run mode the entries are only exported in the requested format and nothing is
+/// sent or recorded.
+fn submit_assignments(
+    api: &mut CeloxisApi,
+    ledger: &mut SubmissionLedger,
+    data_dir: &Path,
+    assignments: &[TaskAssignment],
+    global: &GlobalArgs,
+) -> Result<Vec<TaskAssignment>, Box<dyn Error>> {
+    let open: Vec<&TimeEntry> = assignments
+        .iter()
+        .flat_map(|assignment| assignment.time_entries())
+        .filter(|entry| entry.end.is_none())
+        .collect();
+    if !open.is_empty() && !global.dry_run {
+        return Err(format!(
+            "{:?} started {} is still being tracked, stop it first or pass --open-intervals \
+             exclude, cap or stop",
+            open[0].tags,
+            open[0].start.with_timezone(&Local).format("%Y-%m-%d %H:%M")
+        )
+        .into());
+    }
+
+    let duplicates: Vec<&TimeEntry> = assignments
+        .iter()
+        .flat_map(|assignment| assignment.time_entries())
+        .filter(|entry| ledger.contains(entry))
+        .collect();
+
+    if !duplicates.is_empty() {
+        for entry in &duplicates {
+            status!(
+                "  {}: {} {:?}",
+                if ledger.is_unconfirmed(entry) { "Unconfirmed" } else { "Already submitted" },
+                entry.start.with_timezone(&Local).format("%Y-%m-%d %H:%M"),
+                entry.tags
             );
-            println!(
-                "Task: {} (ID: {})",
-                assignment.celoxis_task.name, assignment.celoxis_task.id
+        }
+        if duplicates.iter().any(|entry| ledger.is_unconfirmed(entry)) {
+            status!(
+                "Intervals Celoxis didn't confirm may be in your timesheet already. If they \
+                 are missing there, remove their records from {:?} to submit them again.",
+                ledger.path()
+            );
+        }
+        return Err(format!(
+            "{} intervals were already submitted to Celoxis, refusing to submit them again",
+            duplicates.len()
+        )
+        .into());
+    }
+
+    let mut all_entries = Vec::new();
+    // The assignment and days behind each entry in all_entries
+    let mut sources = Vec::new();
+
+    // Collect all entries first
+    for (idx, assignment) in assignments.iter().enumerate() {
+        let celoxis_entries = assignment.to_celoxis_entries();
+        if !global.dry_run {
+            status!(
+                "\nPreparing entries for project: {} (Task: {})",
+                assignment.celoxis_project.name, assignment.celoxis_task.name
             );
-            println!("Duration by date:");
-            for (date, duration) in &assignment.total_duration {
-                println!("  {} - {:.2} hours", date, *duration as f64 / 60.0);
+            for entry in &celoxis_entries {
+                status!(
+                    "  {} - {:.2} hours - {}",
+                    entry.date, entry.hours, entry.comments
+                );
             }
-            println!("Summary: {}", assignment.summary);
-            println!("Groups:");
-            for group in &assignment.groups {
-                println!("  - Tags: {:?}", group.tags);
+        }
+        let days = assignment.entry_days().into_iter();
+        sources.extend(days.map(|(_, _, covered)| (idx, covered)));
+        all_entries.extend(celoxis_entries);
+    }
+
+    if global.dry_run {
+        export::write_entries(&all_entries, global.format, global.output.as_deref())?;
+        return Ok(Vec::new());
+    }
+
+    print_totals(assignments);
+    status!("\nSubmitting {} total time entries...", all_entries.len());
+    let outcomes = api.submit_time_entries(&all_entries)?;
+
+    let SubmitResults {
+        mut submitted,
+        failed_dates,
+        failed,
+        unknown,
+    } = record_outcomes(ledger, assignments, &sources, &all_entries, &outcomes);
+    ledger.save()?;
+
+    // An interval crossing midnight is only tagged once all of its days are submitted
+    submitted.retain(|(interval, _)| ledger.is_complete(interval));
+
+    if failed == 0 && unknown == 0 {
+        status!("Successfully submitted all entries");
+    } else {
+        status!(
+            "Submitted {} of {} entries, {} failed",
+            all_entries.len() - failed - unknown,
+            all_entries.len(),
+            failed
+        );
+    }
+    if unknown > 0 {
+        status!(
+            "Warning: check your timesheet in Celoxis for the {} unknown entries. They are \
+             recorded as unconfirmed in {:?} and won't be submitted again until their records \
+             are removed from it.",
+            unknown,
+            ledger.path()
+        );
+    }
+
+    for (assignment, failed) in assignments.iter().zip(&failed_dates) {
+        if failed.len() == assignment.total_duration.len() {
+            continue;
+        }
+        for group in &assignment.groups {
+            if let Err(e) = api.record_assignment(
+                &group.tags,
+                &assignment.celoxis_project.id,
+                &assignment.celoxis_task.id,
+            ) {
+                status!("Warning: could not remember assignment: {}", e);
             }
         }
+    }
 
-        let confirm_submit = Confirm::new("Submit all assignments to Celoxis?")
-            .with_default(true)
-            .prompt()?;
+    // The submission already succeeded, so a tagging problem is only worth a warning
+    if let Err(e) = timew::tag_submitted(data_dir, &submitted, global.tag_intervals) {
+        status!("Warning: could not tag submitted intervals in Timewarrior: {}", e);
+    }
 
-        if confirm_submit {
-            let mut all_entries = Vec::new();
+    Ok(assignments
+        .iter()
+        .zip(&failed_dates)
+        .filter(|(_, failed)| !failed.is_empty())
+        .map(|(assignment, failed)| assignment.only_dates(failed))
+        .collect())
+}
 
-            // Collect all entries first
-            for assignment in &assignments {
-                println!(
-                    "\nPreparing entries for project: {} (Task: {})",
-                    assignment.celoxis_project.name, assignment.celoxis_task.name
-                );
+/// What Celoxis did with the entries of a submission.
+struct SubmitResults<'a> {
+    /// Intervals recorded in the ledger, with the Celoxis id of their entry
+    submitted: Vec<(&'a TimeEntry, Option<&'a str>)>,
+    /// Days of each assignment covered by a rejected entry
+    failed_dates: Vec<Vec<NaiveDate>>,
+    /// Rejected entries, one entry can cover several days
+    failed: usize,
+    unknown: usize,
+}
 
-                let celoxis_entries = assignment.to_celoxis_entries();
-                for entry in &celoxis_entries {
-                    println!(
-                        "  {} - {:.2} hours - {}",
-                        entry.date, entry.hours, entry.comments
+/// Records the intervals of every entry Celoxis saved in the ledger, and those of entries
+/// it didn't report on as unconfirmed. `sources` holds the assignment and covered days of
+/// each entry.
+fn record_outcomes<'a>(
+    ledger: &mut SubmissionLedger,
+    assignments: &'a [TaskAssignment],
+    sources: &[(usize, Vec<NaiveDate>)],
+    entries: &[CeloxisTimeEntry],
+    outcomes: &'a [EntryOutcome],
+) -> SubmitResults<'a> {
+    let mut results = SubmitResults {
+        submitted: Vec::new(),
+        failed_dates: vec![Vec::new(); assignments.len()],
+        failed: 0,
+        unknown: 0,
+    };
+    for ((idx, covered), (entry, outcome)) in sources.iter().zip(entries.iter().zip(outcomes)) {
+        let assignment = &assignments[*idx];
+        match outcome {
+            EntryOutcome::Submitted { id } => {
+                let intervals = covered.iter().flat_map(|date| assignment.time_entries_on(*date));
+                for interval in intervals {
+                    ledger.record(
+                        interval,
+                        &assignment.celoxis_project.id,
+                        &assignment.celoxis_task.id,
+                        id.clone(),
+                    );
+                    results.submitted.push((interval, id.as_deref()));
+                }
+            }
+            EntryOutcome::Failed { reason } => {
+                status!(
+                    "  Failed: {} {:.2} hours on {}: {}",
+                    entry.date, entry.hours, assignment.celoxis_task.name, reason
+                );
+                results.failed_dates[*idx].extend(covered);
+                results.failed += 1;
+            }
+            // Kept out of the next submission, it may already be in Celoxis
+            EntryOutcome::Unknown => {
+                status!(
+                    "  Unknown: {} {:.2} hours on {}, Celoxis didn't say whether it was saved",
+                    entry.date, entry.hours, assignment.celoxis_task.name
+                );
+                for interval in covered.iter().flat_map(|date| assignment.time_entries_on(*date)) {
+                    ledger.record_unconfirmed(
+                        interval,
+                        &assignment.celoxis_project.id,
+                        &assignment.celoxis_task.id,
                     );
                 }
-                all_entries.extend(celoxis_entries);
+                results.unknown += 1;
             }
+        }
+    }
+    results
+}
 
-            println!("\nSubmitting {} total time entries...", all_entries.len());
-            match celoxis.api.submit_time_entries(all_entries) {
-                Ok(_) => println!("Successfully submitted all entries"),
-                Err(e) => println!("Error submitting entries: {}", e),
+/// Shows how much time rounding and adjustments add or take away overall.
+fn print_totals(assignments: &[TaskAssignment]) {
+    let (tracked, submitted) = assignments
+        .iter()
+        .map(TaskAssignment::totals)
+        .fold((0, 0), |(tracked, submitted), (t, s)| (tracked + t, submitted + s));
+    status!(
+        "\nTracked {:.2} hours, submitting {:.2} hours ({:+.2} from rounding and adjustments)",
+        tracked as f64 / 60.0,
+        submitted as f64 / 60.0,
+        (submitted - tracked) as f64 / 60.0
+    );
+}
+
+impl GroupedEntry {
+    fn has_annotations(&self) -> bool {
+        self.entries
+            .values()
+            .flatten()
+            .any(|entry| entry.annotation.is_some())
+    }
+
+    /// The part of the group that falls on the given days, if any.
+    fn only_dates(&self, dates: &[NaiveDate]) -> Option<GroupedEntry> {
+        let entries: HashMap<NaiveDate, Vec<TimeEntry>> = self
+            .entries
+            .iter()
+            .filter(|(date, _)| dates.contains(date))
+            .map(|(date, entries)| (*date, entries.clone()))
+            .collect();
+        if entries.is_empty() {
+            return None;
+        }
+
+        Some(GroupedEntry {
+            key: self.key.clone(),
+            tags: self.tags.clone(),
+            total_duration: self
+                .total_duration
+                .iter()
+                .filter(|(date, _)| dates.contains(date))
+                .map(|(date, duration)| (*date, *duration))
+                .collect(),
+            entries,
+            all_submitted: self.all_submitted,
+        })
+    }
+
+    /// Moves the given days out of this group into a new group with the same key.
+    fn split_off(&mut self, dates: &[NaiveDate]) -> Option<GroupedEntry> {
+        let part = self.only_dates(dates)?;
+        self.entries.retain(|date, _| !dates.contains(date));
+        self.total_duration.retain(|date, _| !dates.contains(date));
+        Some(part)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// Puts groups back among the unassigned ones, joining the parts of a group that was
+/// split across assignments.
+fn return_groups(groups: &mut Vec<GroupedEntry>, returned: Vec<GroupedEntry>) {
+    for group in returned {
+        match groups.iter_mut().find(|existing| existing.key == group.key) {
+            Some(existing) => {
+                for (date, duration) in group.total_duration {
+                    *existing.total_duration.entry(date).or_insert(0) += duration;
+                }
+                for (date, entries) in group.entries {
+                    existing.entries.entry(date).or_default().extend(entries);
+                }
+                existing.all_submitted &= group.all_submitted;
             }
-        } else {
-            println!("Submission cancelled.");
+            None => groups.push(group),
         }
     }
+    groups.sort_by(|a, b| a.key.cmp(&b.key));
+}
 
-    Ok(())
+fn total_duration_by_date(groups: &[GroupedEntry]) -> HashMap<NaiveDate, i64> {
+    let mut total_duration = HashMap::new();
+    for group in groups {
+        for (date, duration) in &group.total_duration {
+            *total_duration.entry(*date).or_insert(0) += duration;
+        }
+    }
+    total_duration
 }
 
 impl TaskAssignment {
+    fn new(
+        groups: Vec<GroupedEntry>,
+        celoxis_project: CeloxisProject,
+        celoxis_task: CeloxisTask,
+        summary: String,
+        user_prefs: &UserPreferences,
+    ) -> Self {
+        TaskAssignment {
+            total_duration: total_duration_by_date(&groups),
+            groups,
+            celoxis_project,
+            celoxis_task,
+            summary,
+            time_code: user_prefs.time_code.clone(),
+            user: user_prefs.username.clone(),
+            rounding: user_prefs.rounding,
+            adjusted: HashMap::new(),
+        }
+    }
+
+    fn time_entries(&self) -> impl Iterator<Item = &TimeEntry> {
+        self.groups
+            .iter()
+            .flat_map(|group| group.entries.values().flatten())
+    }
+
+    /// The intervals booked on one day.
+    fn time_entries_on(&self, date: NaiveDate) -> impl Iterator<Item = &TimeEntry> {
+        self.groups
+            .iter()
+            .filter_map(move |group| group.entries.get(&date))
+            .flatten()
+    }
+
+    /// The days with time in this assignment, in order.
+    fn dates(&self) -> Vec<NaiveDate> {
+        let mut dates: Vec<NaiveDate> = self.total_duration.keys().copied().collect();
+        dates.sort();
+        dates
+    }
+
+    /// The same assignment restricted to the given days.
+    fn only_dates(&self, dates: &[NaiveDate]) -> TaskAssignment {
+        let groups: Vec<GroupedEntry> = self
+            .groups
+            .iter()
+            .filter_map(|group| group.only_dates(dates))
+            .collect();
+
+        TaskAssignment {
+            total_duration: total_duration_by_date(&groups),
+            groups,
+            celoxis_project: self.celoxis_project.clone(),
+            celoxis_task: self.celoxis_task.clone(),
+            summary: self.summary.clone(),
+            time_code: self.time_code.clone(),
+            user: self.user.clone(),
+            rounding: self.rounding,
+            adjusted: self
+                .adjusted
+                .iter()
+                .filter(|(date, _)| dates.contains(date))
+                .map(|(date, minutes)| (*date, *minutes))
+                .collect(),
+        }
+    }
+
+    /// Recomputes the totals after groups were added or removed. Adjusted hours are
+    /// dropped for days whose tracked time changed.
+    fn refresh_totals(&mut self) {
+        let total_duration = total_duration_by_date(&self.groups);
+        self.adjusted
+            .retain(|date, _| total_duration.get(date) == self.total_duration.get(date));
+        self.total_duration = total_duration;
+    }
+
+    /// The tracked time of every day after rounding at the configured level, with the
+    /// minimum applied to each day.
+    fn rounded_minutes(&self) -> HashMap<NaiveDate, i64> {
+        let dates = self.dates();
+        let rounded: Vec<(NaiveDate, i64)> = match self.rounding.level {
+            RoundingLevel::Interval => dates
+                .into_iter()
+                .map(|date| {
+                    let minutes = self
+                        .time_entries_on(date)
+                        .map(|entry| self.rounding.apply(entry.minutes()))
+                        .sum();
+                    (date, minutes)
+                })
+                .collect(),
+            RoundingLevel::Day => dates
+                .into_iter()
+                .map(|date| (date, self.rounding.apply(self.total_duration[&date])))
+                .collect(),
+            RoundingLevel::Assignment => {
+                // Rounding the running total keeps every day a multiple of the increment
+                // while the days add up to the rounded total
+                let mut tracked = 0;
+                let mut submitted = 0;
+                dates
+                    .into_iter()
+                    .map(|date| {
+                        tracked += self.total_duration[&date];
+                        let rounded = self.rounding.apply(tracked);
+                        let minutes = rounded - submitted;
+                        submitted = rounded;
+                        (date, minutes)
+                    })
+                    .collect()
+            }
+        };
+
+        // The minimum is charged once per entry, not per interval
+        rounded
+            .into_iter()
+            .map(|(date, minutes)| {
+                let tracked = self.total_duration[&date];
+                (date, self.rounding.at_least_minimum(tracked, minutes))
+            })
+            .collect()
+    }
+
+    /// The minutes submitted for a day: the adjusted ones, or the rounded tracked time.
+    fn minutes_on(&self, date: NaiveDate) -> i64 {
+        match self.adjusted.get(&date) {
+            Some(minutes) => *minutes,
+            None => self.rounded_minutes()[&date],
+        }
+    }
+
+    /// Tracked and submitted minutes of the whole assignment.
+    fn totals(&self) -> (i64, i64) {
+        let tracked = self.total_duration.values().sum();
+        let submitted = self.dates().into_iter().map(|date| self.minutes_on(date)).sum();
+        (tracked, submitted)
+    }
+
+    /// The days that become time entries, with their minutes and the days whose intervals
+    /// each entry covers. Rounding the assignment's total can leave a day without any
+    /// minutes, its intervals then go with the next entry, or the last one.
+    fn entry_days(&self) -> Vec<(NaiveDate, i64, Vec<NaiveDate>)> {
+        let mut days: Vec<(NaiveDate, i64, Vec<NaiveDate>)> = Vec::new();
+        let mut covered = Vec::new();
+        for date in self.dates() {
+            covered.push(date);
+            let minutes = self.minutes_on(date);
+            if minutes > 0 {
+                days.push((date, minutes, std::mem::take(&mut covered)));
+            }
+        }
+        if let Some((_, _, last)) = days.last_mut() {
+            last.extend(covered);
+        }
+        days
+    }
+
+    fn display(&self) {
+        status!(
+            "\nProject: {} (ID: {})",
+            self.celoxis_project.name, self.celoxis_project.id
+        );
+        status!(
+            "Task: {} (ID: {})",
+            self.celoxis_task.name, self.celoxis_task.id
+        );
+        status!("Duration by date:");
+        let rounded = self.rounded_minutes();
+        for date in self.dates() {
+            let tracked = self.total_duration[&date];
+            match self.adjusted.get(&date) {
+                Some(minutes) => status!(
+                    "  {} - {:.2} hours (adjusted, tracked {:.2})",
+                    date,
+                    *minutes as f64 / 60.0,
+                    tracked as f64 / 60.0
+                ),
+                None if rounded[&date] != tracked => status!(
+                    "  {} - {:.2} hours (rounded, tracked {:.2})",
+                    date,
+                    rounded[&date] as f64 / 60.0,
+                    tracked as f64 / 60.0
+                ),
+                None => status!("  {} - {:.2} hours", date, tracked as f64 / 60.0),
+            }
+        }
+        let (tracked, submitted) = self.totals();
+        if tracked != submitted {
+            status!(
+                "Total: {:.2} hours tracked, {:.2} hours submitted",
+                tracked as f64 / 60.0,
+                submitted as f64 / 60.0
+            );
+        }
+        let has_annotations = self.groups.iter().any(GroupedEntry::has_annotations);
+        if self.summary.trim().is_empty() && !has_annotations {
+            status!("Summary: (not entered yet)");
+        } else if self.summary.trim().is_empty() {
+            status!("Summary: (from annotations)");
+        } else {
+            status!("Summary: {}", self.summary);
+        }
+        status!("Groups:");
+        for group in &self.groups {
+            status!("  - {}", group.key);
+        }
+    }
+
     fn to_celoxis_entries(&self) -> Vec<CeloxisTimeEntry> {
         let mut celoxis_entries = Vec::new();
 
-        for (date, duration) in &self.total_duration {
-            let hours = ((*duration as f64 / 60.0) * 100.0).round() / 100.0; // Round to 2 decimal places
+        // Same order as entry_days(), submission results are matched up by position
+        for (date, duration, _) in self.entry_days() {
+            let hours = ((duration as f64 / 60.0) * 100.0).round() / 100.0; // Round to 2 decimal places
 
             celoxis_entries.push(CeloxisTimeEntry {
                 date: date.format("%Y-%m-%d").to_string(),
@@ -786,10 +2128,166 @@ impl TaskAssignment {
                 user: self.user.clone(),
                 task: self.celoxis_task.id.clone(),
                 state: 0,
-                comments: self.summary.clone(),
+                comments: self.comments_for(date),
             });
         }
 
         celoxis_entries
     }
+
+    /// The comment for one day: the summary if one was given, otherwise that day's
+    /// annotations in chronological order with duplicates removed.
+    fn comments_for(&self, date: NaiveDate) -> String {
+        if !self.summary.trim().is_empty() {
+            return self.summary.clone();
+        }
+
+        let mut entries: Vec<&TimeEntry> = self
+            .groups
+            .iter()
+            .filter_map(|group| group.entries.get(&date))
+            .flatten()
+            .collect();
+        entries.sort_by_key(|entry| entry.start);
+
+        let mut annotations: Vec<&str> = Vec::new();
+        for annotation in entries.iter().filter_map(|entry| entry.annotation.as_deref()) {
+            if !annotations.contains(&annotation) {
+                annotations.push(annotation);
+            }
+        }
+        annotations.join("; ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2026, 3, day).unwrap()
+    }
+
+    /// A group with one interval of the given minutes on each day.
+    fn group(tag: &str, days: &[(u32, i64)]) -> GroupedEntry {
+        let mut total_duration = HashMap::new();
+        let mut entries = HashMap::new();
+        for (day, minutes) in days {
+            let start = date(*day).and_hms_opt(9, 0, 0).unwrap().and_utc();
+            let end = start + chrono::Duration::minutes(*minutes);
+            let id = format!("{}-{}", tag, day);
+            let mut entry = TimeEntry::new(id, start, Some(end), vec![tag.into()], None);
+            entry.day = date(*day);
+            total_duration.insert(date(*day), *minutes);
+            entries.insert(date(*day), vec![entry]);
+        }
+        GroupedEntry {
+            key: GroupKey::Tags(vec![tag.to_string()]),
+            tags: vec![tag.to_string()],
+            total_duration,
+            entries,
+            all_submitted: false,
+        }
+    }
+
+    fn assignment(group: GroupedEntry, rounding: Rounding) -> TaskAssignment {
+        let groups = vec![group];
+        TaskAssignment {
+            total_duration: total_duration_by_date(&groups),
+            groups,
+            celoxis_project: CeloxisProject {
+                id: "p1".into(),
+                name: "Project".into(),
+                description: None,
+                state: "Active".into(),
+            },
+            celoxis_task: CeloxisTask {
+                id: "t1".into(),
+                name: "Task".into(),
+            },
+            summary: "Work".into(),
+            time_code: "Regular".into(),
+            user: "me".into(),
+            rounding,
+            adjusted: HashMap::new(),
+        }
+    }
+
+    fn empty_ledger() -> SubmissionLedger {
+        SubmissionLedger::load(&std::env::temp_dir().join("tw-upload-tests-no-ledger")).unwrap()
+    }
+
+    #[test]
+    fn days_rounded_to_nothing_go_with_the_next_entry() {
+        let rounding = Rounding {
+            increment: 60,
+            mode: config::RoundingMode::Down,
+            ..Rounding::default()
+        };
+        let assignment = assignment(group("dev", &[(2, 30), (3, 90)]), rounding);
+
+        let days = assignment.entry_days();
+        assert_eq!(days, vec![(date(3), 60, vec![date(2), date(3)])]);
+    }
+
+    #[test]
+    fn failed_merged_entry_counts_once() {
+        let rounding = Rounding {
+            increment: 60,
+            mode: config::RoundingMode::Down,
+            ..Rounding::default()
+        };
+        let assignments = vec![
+            assignment(group("dev", &[(2, 30), (3, 90)]), rounding),
+            assignment(group("ops", &[(2, 60)]), rounding),
+        ];
+        let mut sources = Vec::new();
+        let mut entries = Vec::new();
+        for (idx, assignment) in assignments.iter().enumerate() {
+            sources.extend(assignment.entry_days().into_iter().map(|(_, _, days)| (idx, days)));
+            entries.extend(assignment.to_celoxis_entries());
+        }
+        let outcomes = vec![
+            EntryOutcome::Failed {
+                reason: "HTTP 400".into(),
+            },
+            EntryOutcome::Submitted {
+                id: Some("42".into()),
+            },
+        ];
+
+        let mut ledger = empty_ledger();
+        let results = record_outcomes(&mut ledger, &assignments, &sources, &entries, &outcomes);
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!(results.failed, 1);
+        assert_eq!(results.unknown, 0);
+        assert_eq!(results.failed_dates, vec![vec![date(2), date(3)], vec![]]);
+        assert_eq!(results.submitted.len(), 1);
+        assert_eq!(results.submitted[0].1, Some("42"));
+        assert!(!ledger.contains(&assignments[0].groups[0].entries[&date(2)][0]));
+        assert!(ledger.contains(&assignments[1].groups[0].entries[&date(2)][0]));
+    }
+
+    #[test]
+    fn unknown_entries_are_recorded_unconfirmed() {
+        let assignments = vec![assignment(group("dev", &[(2, 60)]), Rounding::default())];
+        let sources = vec![(0, vec![date(2)])];
+        let entries = assignments[0].to_celoxis_entries();
+        let outcomes = vec![EntryOutcome::Unknown];
+
+        let mut ledger = empty_ledger();
+        let results = record_outcomes(&mut ledger, &assignments, &sources, &entries, &outcomes);
+
+        let interval = &assignments[0].groups[0].entries[&date(2)][0];
+        assert_eq!(results.unknown, 1);
+        assert!(results.submitted.is_empty());
+        assert!(ledger.contains(interval));
+        assert!(ledger.is_unconfirmed(interval));
+        assert!(!ledger.is_complete(interval));
+
+        let mut loaded = vec![interval.clone()];
+        ledger.apply(&mut loaded);
+        assert!(!loaded[0].submitted);
+    }
 }
@@ -0,0 +1,393 @@
+use chrono::{DateTime, NaiveDateTime, Utc};
+use std::error::Error;
+use std::fmt;
+
+const TIMEW_DATE_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+/// An interval as serialized in Timewarrior's `YYYY-MM.data` files:
+///
+/// ```text
+/// inc 20240101T090000Z - 20240101T100000Z # tag "tag with spaces" # "annotation"
+/// ```
+///
+/// The end is omitted for the open interval, the tag list may be empty
+/// (`inc ... # # "annotation"`) and both `#` sections are optional. A tag that is exactly
+/// `#` has to be quoted, written bare it is taken as the separator before the annotation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParsedInterval {
+    pub start: DateTime<Utc>,
+    pub end: Option<DateTime<Utc>>,
+    pub tags: Vec<String>,
+    pub annotation: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    Empty,
+    NotAnInterval,
+    MissingStart,
+    InvalidTimestamp(String),
+    UnterminatedQuote { column: usize },
+    UnexpectedQuote { column: usize },
+    UnexpectedToken { column: usize, token: String },
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Empty => write!(f, "Empty line"),
+            ParseError::NotAnInterval => write!(f, "Line doesn't start with 'inc'"),
+            ParseError::MissingStart => write!(f, "Interval has no start time"),
+            ParseError::InvalidTimestamp(value) => write!(f, "Invalid timestamp '{}'", value),
+            ParseError::UnterminatedQuote { column } => {
+                write!(f, "Quote opened at column {} is never closed", column)
+            }
+            ParseError::UnexpectedQuote { column } => {
+                write!(f, "Unexpected quote at column {}", column)
+            }
+            ParseError::UnexpectedToken { column, token } => {
+                write!(f, "Unexpected '{}' at column {}", token, column)
+            }
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+#[derive(Debug, PartialEq)]
+enum TokenKind {
+    Word(String),
+    Quoted(String),
+    Hash,
+}
+
+#[derive(Debug)]
+struct Token {
+    kind: TokenKind,
+    column: usize,
+}
+
+/// Splits a line into whitespace separated words, quoted strings and `#` separators.
+///
+/// Inside quotes `\"` and `\\` are escapes. A quote has to start and end a token, so
+/// `"a"b` and `a"b"` are rejected instead of being merged into one tag.
+fn tokenize(line: &str) -> Result<Vec<Token>, ParseError> {
+    let mut tokens = Vec::new();
+    let mut chars = line.chars().enumerate().peekable();
+
+    while let Some(&(idx, c)) = chars.peek() {
+        let column = idx + 1;
+
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+
+        if c == '"' {
+            chars.next();
+            let mut value = String::new();
+            let mut closed = false;
+
+            while let Some((_, c)) = chars.next() {
+                match c {
+                    '\\' => match chars.peek() {
+                        Some(&(_, next)) if next == '"' || next == '\\' => {
+                            value.push(next);
+                            chars.next();
+                        }
+                        _ => value.push('\\'),
+                    },
+                    '"' => {
+                        closed = true;
+                        break;
+                    }
+                    _ => value.push(c),
+                }
+            }
+
+            if !closed {
+                return Err(ParseError::UnterminatedQuote { column });
+            }
+            if let Some(&(idx, c)) = chars.peek() {
+                if !c.is_whitespace() {
+                    return Err(ParseError::UnexpectedQuote { column: idx + 1 });
+                }
+            }
+
+            tokens.push(Token {
+                kind: TokenKind::Quoted(value),
+                column,
+            });
+            continue;
+        }
+
+        let mut word = String::new();
+        while let Some(&(idx, c)) = chars.peek() {
+            if c.is_whitespace() {
+                break;
+            }
+            if c == '"' {
+                return Err(ParseError::UnexpectedQuote { column: idx + 1 });
+            }
+            word.push(c);
+            chars.next();
+        }
+
+        let kind = if word == "#" {
+            TokenKind::Hash
+        } else {
+            TokenKind::Word(word)
+        };
+        tokens.push(Token { kind, column });
+    }
+
+    Ok(tokens)
+}
+
+fn parse_timestamp(value: &str) -> Result<DateTime<Utc>, ParseError> {
+    let naive = NaiveDateTime::parse_from_str(value, TIMEW_DATE_FORMAT)
+        .map_err(|_| ParseError::InvalidTimestamp(value.to_string()))?;
+    Ok(DateTime::<Utc>::from_naive_utc_and_offset(naive, Utc))
+}
+
+fn unexpected(token: &Token) -> ParseError {
+    let text = match &token.kind {
+        TokenKind::Word(word) => word.clone(),
+        TokenKind::Quoted(value) => format!("\"{}\"", value),
+        TokenKind::Hash => "#".to_string(),
+    };
+    ParseError::UnexpectedToken {
+        column: token.column,
+        token: text,
+    }
+}
+
+/// Parses one line of a Timewarrior data file.
+pub fn parse_interval(line: &str) -> Result<ParsedInterval, ParseError> {
+    if line.trim().is_empty() {
+        return Err(ParseError::Empty);
+    }
+
+    let tokens = tokenize(line)?;
+    let mut tokens = tokens.iter().peekable();
+
+    match tokens.next().map(|t| &t.kind) {
+        Some(TokenKind::Word(word)) if word == "inc" => {}
+        _ => return Err(ParseError::NotAnInterval),
+    }
+
+    let start = match tokens.next() {
+        Some(Token {
+            kind: TokenKind::Word(value),
+            ..
+        }) => parse_timestamp(value)?,
+        Some(token) if token.kind == TokenKind::Hash => return Err(ParseError::MissingStart),
+        Some(token) => return Err(unexpected(token)),
+        None => return Err(ParseError::MissingStart),
+    };
+
+    let mut end = None;
+    if let Some(Token {
+        kind: TokenKind::Word(dash),
+        ..
+    }) = tokens.peek()
+    {
+        if dash == "-" {
+            tokens.next();
+            end = match tokens.next() {
+                Some(Token {
+                    kind: TokenKind::Word(value),
+                    ..
+                }) => Some(parse_timestamp(value)?),
+                Some(token) => return Err(unexpected(token)),
+                None => return Err(ParseError::InvalidTimestamp(String::new())),
+            };
+        }
+    }
+
+    let mut tags = Vec::new();
+    let mut annotation = None;
+
+    match tokens.next() {
+        None => {}
+        Some(token) if token.kind == TokenKind::Hash => {
+            for token in tokens.by_ref() {
+                match &token.kind {
+                    TokenKind::Word(tag) | TokenKind::Quoted(tag) => tags.push(tag.clone()),
+                    TokenKind::Hash => {
+                        annotation = Some(String::new());
+                        break;
+                    }
+                }
+            }
+        }
+        Some(token) => return Err(unexpected(token)),
+    }
+
+    // Timewarrior quotes the annotation, but tolerate a bare one written by hand
+    if let Some(text) = annotation.as_mut() {
+        let mut parts = Vec::new();
+        for token in tokens {
+            match &token.kind {
+                TokenKind::Word(part) | TokenKind::Quoted(part) => parts.push(part.as_str()),
+                TokenKind::Hash => return Err(unexpected(token)),
+            }
+        }
+        *text = parts.join(" ");
+    }
+
+    Ok(ParsedInterval {
+        start,
+        end,
+        tags,
+        annotation: annotation.filter(|text| !text.is_empty()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(value: &str) -> DateTime<Utc> {
+        parse_timestamp(value).unwrap()
+    }
+
+    fn tags(line: &str) -> Vec<String> {
+        parse_interval(line).unwrap().tags
+    }
+
+    #[test]
+    fn closed_interval_with_tags_and_annotation() {
+        let line =
+            r#"inc 20240101T090000Z - 20240101T100000Z # acme "project:acme" # "fixing things""#;
+        assert_eq!(
+            parse_interval(line).unwrap(),
+            ParsedInterval {
+                start: utc("20240101T090000Z"),
+                end: Some(utc("20240101T100000Z")),
+                tags: vec!["acme".to_string(), "project:acme".to_string()],
+                annotation: Some("fixing things".to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn open_interval_without_tags() {
+        let interval = parse_interval("inc 20240101T090000Z").unwrap();
+        assert_eq!(interval.start, utc("20240101T090000Z"));
+        assert_eq!(interval.end, None);
+        assert!(interval.tags.is_empty());
+        assert_eq!(interval.annotation, None);
+
+        let interval = parse_interval("inc 20240101T090000Z # dev").unwrap();
+        assert_eq!(interval.end, None);
+        assert_eq!(interval.tags, vec!["dev"]);
+    }
+
+    #[test]
+    fn annotation_without_tags() {
+        let interval =
+            parse_interval(r#"inc 20240101T090000Z - 20240101T100000Z # # "call with Bob""#)
+                .unwrap();
+        assert!(interval.tags.is_empty());
+        assert_eq!(interval.annotation.as_deref(), Some("call with Bob"));
+    }
+
+    #[test]
+    fn tags_with_spaces_and_escaped_quotes() {
+        assert_eq!(
+            tags(r#"inc 20240101T090000Z - 20240101T100000Z # "tag with spaces" "say \"hi\"""#),
+            vec!["tag with spaces", r#"say "hi""#]
+        );
+        assert_eq!(
+            tags(r#"inc 20240101T090000Z - 20240101T100000Z # "back\\slash""#),
+            vec![r"back\slash"]
+        );
+    }
+
+    #[test]
+    fn hash_inside_tags_and_annotation() {
+        let line =
+            r##"inc 20240101T090000Z - 20240101T100000Z # issue#12 "#" "a # b" # "see #12""##;
+        let interval = parse_interval(line).unwrap();
+        assert_eq!(interval.tags, vec!["issue#12", "#", "a # b"]);
+        assert_eq!(interval.annotation.as_deref(), Some("see #12"));
+    }
+
+    #[test]
+    fn bare_hash_tag_is_the_annotation_separator() {
+        // Meant as the tags `#` and `dev`, read as an annotation without tags
+        let interval = parse_interval("inc 20240101T090000Z - 20240101T100000Z # # dev").unwrap();
+        assert!(interval.tags.is_empty());
+        assert_eq!(interval.annotation.as_deref(), Some("dev"));
+
+        // After the annotation has started a bare `#` can't be placed at all
+        assert_eq!(
+            parse_interval("inc 20240101T090000Z - 20240101T100000Z # dev # # note"),
+            Err(ParseError::UnexpectedToken {
+                column: 49,
+                token: "#".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn unicode_tags_and_annotation() {
+        let line =
+            r#"inc 20240101T090000Z - 20240101T100000Z # café "日本語 タグ" 🚀 # "Überprüfung""#;
+        let interval = parse_interval(line).unwrap();
+        assert_eq!(interval.tags, vec!["café", "日本語 タグ", "🚀"]);
+        assert_eq!(interval.annotation.as_deref(), Some("Überprüfung"));
+    }
+
+    #[test]
+    fn bare_annotation_written_by_hand() {
+        let interval =
+            parse_interval("inc 20240101T090000Z - 20240101T100000Z # dev # fixing things")
+                .unwrap();
+        assert_eq!(interval.annotation.as_deref(), Some("fixing things"));
+    }
+
+    #[test]
+    fn unterminated_quote() {
+        assert_eq!(
+            parse_interval(r#"inc 20240101T090000Z # "never closed"#),
+            Err(ParseError::UnterminatedQuote { column: 24 })
+        );
+    }
+
+    #[test]
+    fn quote_inside_a_token() {
+        assert_eq!(
+            parse_interval(r#"inc 20240101T090000Z # a"b""#),
+            Err(ParseError::UnexpectedQuote { column: 25 })
+        );
+        assert_eq!(
+            parse_interval(r#"inc 20240101T090000Z # "a"b"#),
+            Err(ParseError::UnexpectedQuote { column: 27 })
+        );
+    }
+
+    #[test]
+    fn missing_start() {
+        assert_eq!(parse_interval("inc"), Err(ParseError::MissingStart));
+        assert_eq!(parse_interval("inc # dev"), Err(ParseError::MissingStart));
+    }
+
+    #[test]
+    fn lines_that_are_no_intervals() {
+        assert_eq!(parse_interval("   "), Err(ParseError::Empty));
+        assert_eq!(
+            parse_interval("exc 20240101T090000Z"),
+            Err(ParseError::NotAnInterval)
+        );
+        assert_eq!(
+            parse_interval("inc 2024-01-01 # dev"),
+            Err(ParseError::InvalidTimestamp("2024-01-01".to_string()))
+        );
+        assert_eq!(
+            parse_interval("inc 20240101T090000Z -"),
+            Err(ParseError::InvalidTimestamp(String::new()))
+        );
+    }
+}
@@ -0,0 +1,294 @@
+use chrono::NaiveDate;
+use inquire::list_option::ListOption;
+use inquire::validator::Validation;
+use inquire::{MultiSelect, Select, Text};
+use std::error::Error;
+
+use crate::celoxis::{CeloxisProject, CeloxisTask, UserPreferences};
+use crate::{
+    assign_groups, print_totals, return_groups, CeloxisData, GroupedEntry, TaskAssignment,
+};
+
+const CHANGE_TASK: &str = "Change the task of an assignment";
+const EDIT_SUMMARY: &str = "Edit the summary of an assignment";
+const ADJUST_HOURS: &str = "Adjust the hours of a day";
+const SPLIT: &str = "Split an assignment across two tasks";
+const DROP: &str = "Drop an assignment";
+const CANCEL: &str = "Cancel";
+
+/// Shows the assignments and lets the user change them until they are submitted.
+/// Dropped assignments return their groups to `groups`, from where they can be assigned
+/// again. Every change is autosaved. Returns whether to go ahead with the submission.
+pub fn review_assignments(
+    celoxis: &mut CeloxisData,
+    assignments: &mut Vec<TaskAssignment>,
+    groups: &mut Vec<GroupedEntry>,
+    user_prefs: &UserPreferences,
+    autosave: &dyn Fn(&[TaskAssignment]),
+    dry_run: bool,
+) -> Result<bool, Box<dyn Error>> {
+    let submit = if dry_run {
+        "Export all assignments without submitting them (dry run)"
+    } else {
+        "Submit all assignments to Celoxis"
+    };
+
+    loop {
+        status!("\nReady to process {} task assignments", assignments.len());
+        status!("\nAssignments to be processed:");
+        for (idx, assignment) in assignments.iter().enumerate() {
+            status!("\nAssignment {}", idx + 1);
+            assignment.display();
+        }
+        if !assignments.is_empty() {
+            print_totals(assignments);
+        }
+        if !groups.is_empty() {
+            status!("\n{} groups are not assigned yet", groups.len());
+        }
+
+        let assign = format!("Assign the {} unassigned groups", groups.len());
+        let mut options = Vec::new();
+        if !assignments.is_empty() {
+            options.extend([submit, CHANGE_TASK, EDIT_SUMMARY, ADJUST_HOURS, SPLIT, DROP]);
+        }
+        if !groups.is_empty() {
+            options.push(&assign);
+        }
+        options.push(CANCEL);
+
+        let choice = Select::new("What would you like to do?", options).prompt()?;
+        if choice == submit {
+            return Ok(true);
+        } else if choice == CANCEL {
+            return Ok(false);
+        } else if choice == assign {
+            assign_groups(celoxis, groups, user_prefs, assignments, autosave)?;
+            continue;
+        }
+
+        let Some(idx) = select_assignment(assignments)? else {
+            continue;
+        };
+        match choice {
+            CHANGE_TASK => {
+                if let Some((project, task)) = select_task(celoxis, &assignments[idx])? {
+                    assignments[idx].celoxis_project = project;
+                    assignments[idx].celoxis_task = task;
+                }
+            }
+            EDIT_SUMMARY => edit_summary(&mut assignments[idx])?,
+            ADJUST_HOURS => adjust_hours(&mut assignments[idx])?,
+            SPLIT => {
+                if let Some(part) = split_assignment(celoxis, &mut assignments[idx], user_prefs)? {
+                    assignments.insert(idx + 1, part);
+                }
+            }
+            DROP => {
+                let dropped = assignments.remove(idx);
+                status!(
+                    "Returned {} groups to the unassigned ones",
+                    dropped.groups.len()
+                );
+                return_groups(groups, dropped.groups);
+            }
+            _ => unreachable!(),
+        }
+        autosave(assignments);
+    }
+}
+
+fn select_assignment(assignments: &[TaskAssignment]) -> Result<Option<usize>, Box<dyn Error>> {
+    if assignments.len() == 1 {
+        return Ok(Some(0));
+    }
+
+    let options: Vec<String> = assignments
+        .iter()
+        .enumerate()
+        .map(|(idx, assignment)| {
+            let minutes: i64 = assignment
+                .dates()
+                .into_iter()
+                .map(|date| assignment.minutes_on(date))
+                .sum();
+            format!(
+                "{} - {} ({}) - {:.2}h",
+                idx + 1,
+                assignment.celoxis_task.name,
+                assignment.celoxis_project.name,
+                minutes as f64 / 60.0
+            )
+        })
+        .collect();
+
+    let selection = Select::new("Which assignment?", options.clone()).prompt_skippable()?;
+    Ok(selection.and_then(|selection| options.iter().position(|x| x == &selection)))
+}
+
+/// Asks for a project and task, starting at the ones of the assignment.
+fn select_task(
+    celoxis: &mut CeloxisData,
+    assignment: &TaskAssignment,
+) -> Result<Option<(CeloxisProject, CeloxisTask)>, Box<dyn Error>> {
+    celoxis.selected_project = None;
+    celoxis.selected_tasks.clear();
+
+    celoxis.select_project(Some(&assignment.celoxis_project.id))?;
+    let Some(project) = celoxis.selected_project.clone() else {
+        return Ok(None);
+    };
+    let suggested_task = Some(assignment.celoxis_task.id.as_str())
+        .filter(|_| project.id == assignment.celoxis_project.id);
+    celoxis.select_tasks(suggested_task)?;
+
+    match celoxis.selected_tasks.first() {
+        Some(task) => Ok(Some((project, task.clone()))),
+        None => {
+            status!("No task selected, the assignment is unchanged");
+            Ok(None)
+        }
+    }
+}
+
+fn edit_summary(assignment: &mut TaskAssignment) -> Result<(), Box<dyn Error>> {
+    let has_annotations = assignment.groups.iter().any(GroupedEntry::has_annotations);
+    let prompt = if has_annotations {
+        "Work summary (empty to use the annotations):"
+    } else {
+        "Work summary:"
+    };
+
+    assignment.summary = Text::new(prompt)
+        .with_initial_value(&assignment.summary)
+        .with_validator(move |input: &str| {
+            if input.trim().is_empty() && !has_annotations {
+                Ok(Validation::Invalid("Summary cannot be empty".into()))
+            } else {
+                Ok(Validation::Valid)
+            }
+        })
+        .prompt()?;
+    Ok(())
+}
+
+/// Replaces the hours submitted for one day. Entering the rounded tracked time again
+/// removes the adjustment.
+fn adjust_hours(assignment: &mut TaskAssignment) -> Result<(), Box<dyn Error>> {
+    let dates = assignment.dates();
+    let options: Vec<String> = dates
+        .iter()
+        .map(|date| {
+            format!(
+                "{} - {:.2} hours",
+                date,
+                assignment.minutes_on(*date) as f64 / 60.0
+            )
+        })
+        .collect();
+    let Some(selection) = Select::new("Which day?", options.clone()).prompt_skippable()? else {
+        return Ok(());
+    };
+    let date = dates[options.iter().position(|x| x == &selection).unwrap()];
+
+    let current = format!("{:.2}", assignment.minutes_on(date) as f64 / 60.0);
+    let hours = Text::new(&format!("Hours on {}:", date))
+        .with_initial_value(&current)
+        .with_validator(|input: &str| match input.trim().parse::<f64>() {
+            Ok(hours) if hours > 0.0 && hours <= 24.0 => Ok(Validation::Valid),
+            _ => Ok(Validation::Invalid(
+                "Enter the hours as a number between 0 and 24".into(),
+            )),
+        })
+        .prompt()?;
+    let minutes = (hours.trim().parse::<f64>()? * 60.0).round() as i64;
+
+    if minutes == assignment.rounded_minutes()[&date] {
+        assignment.adjusted.remove(&date);
+    } else {
+        assignment.adjusted.insert(date, minutes);
+    }
+    Ok(())
+}
+
+/// Moves some days of some groups to a new assignment on another task. Returns the new
+/// assignment, or nothing if the user backed out.
+fn split_assignment(
+    celoxis: &mut CeloxisData,
+    assignment: &mut TaskAssignment,
+    user_prefs: &UserPreferences,
+) -> Result<Option<TaskAssignment>, Box<dyn Error>> {
+    // Each day of each group can go either way
+    let mut parts: Vec<(usize, NaiveDate)> = Vec::new();
+    for (idx, group) in assignment.groups.iter().enumerate() {
+        let mut dates: Vec<NaiveDate> = group.total_duration.keys().copied().collect();
+        dates.sort();
+        parts.extend(dates.into_iter().map(|date| (idx, date)));
+    }
+    if parts.len() < 2 {
+        status!("This assignment covers a single group on a single day, there is nothing to split");
+        return Ok(None);
+    }
+
+    let options: Vec<String> = parts
+        .iter()
+        .map(|(idx, date)| {
+            let group = &assignment.groups[*idx];
+            format!(
+                "{} - {} - {:.2}h",
+                date,
+                group.key,
+                group.total_duration[date] as f64 / 60.0
+            )
+        })
+        .collect();
+    let part_count = options.len();
+    let selections = MultiSelect::new(
+        "Select what moves to the other task (Space to select, Enter to confirm):",
+        options.clone(),
+    )
+    .with_validator(move |selections: &[ListOption<&String>]| {
+        if selections.is_empty() || selections.len() == part_count {
+            Ok(Validation::Invalid(
+                "Select some, but not all, of the entries".into(),
+            ))
+        } else {
+            Ok(Validation::Valid)
+        }
+    })
+    .prompt_skippable()?;
+    let Some(selections) = selections else {
+        return Ok(None);
+    };
+
+    let Some((project, task)) = select_task(celoxis, assignment)? else {
+        return Ok(None);
+    };
+
+    let moved: Vec<(usize, NaiveDate)> = selections
+        .iter()
+        .filter_map(|selection| options.iter().position(|x| x == selection))
+        .map(|pos| parts[pos])
+        .collect();
+    let mut moved_groups = Vec::new();
+    for (idx, group) in assignment.groups.iter_mut().enumerate() {
+        let dates: Vec<NaiveDate> = moved
+            .iter()
+            .filter(|(group_idx, _)| *group_idx == idx)
+            .map(|(_, date)| *date)
+            .collect();
+        moved_groups.extend(group.split_off(&dates));
+    }
+    assignment.groups.retain(|group| !group.is_empty());
+    assignment.refresh_totals();
+
+    let mut part = TaskAssignment::new(
+        moved_groups,
+        project,
+        task,
+        assignment.summary.clone(),
+        user_prefs,
+    );
+    part.time_code = assignment.time_code.clone();
+    Ok(Some(part))
+}
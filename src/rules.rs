@@ -0,0 +1,119 @@
+use regex::Regex;
+use serde::Deserialize;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const RULES_FILE: &str = "celoxis_rules.json";
+
+/// Conditions a group's tags must meet for a rule to apply. All given conditions must hold.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct RuleMatch {
+    /// Every one of these tags must be present
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Value of a `project:` tag
+    pub project: Option<String>,
+    /// Value of a `description:` tag
+    pub description: Option<String>,
+    /// At least one tag must match this regular expression
+    pub regex: Option<String>,
+}
+
+/// Maps groups of Timewarrior intervals to a Celoxis task, e.g.
+///
+/// ```json
+/// { "match": { "project": "acme", "tags": ["meeting"] },
+///   "project_id": "1234", "task_id": "5678",
+///   "time_code": "meetings", "summary": "Meetings for {project}" }
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+pub struct MappingRule {
+    #[serde(rename = "match")]
+    pub conditions: RuleMatch,
+    pub project_id: String,
+    pub task_id: String,
+    pub time_code: Option<String>,
+    /// Summary template, `{project}`, `{description}` and `{tags}` are replaced
+    pub summary: Option<String>,
+    #[serde(skip)]
+    regex: Option<Regex>,
+}
+
+impl MappingRule {
+    fn tag_value<'a>(tags: &'a [String], prefix: &str) -> Option<&'a str> {
+        tags.iter()
+            .find_map(|tag| tag.strip_prefix(prefix))
+            .map(str::trim)
+    }
+
+    pub fn matches(&self, tags: &[String]) -> bool {
+        let conditions = &self.conditions;
+
+        conditions.tags.iter().all(|tag| tags.contains(tag))
+            && conditions
+                .project
+                .as_deref()
+                .is_none_or(|project| Self::tag_value(tags, "project:") == Some(project))
+            && conditions.description.as_deref().is_none_or(|description| {
+                Self::tag_value(tags, "description:") == Some(description)
+            })
+            && self
+                .regex
+                .as_ref()
+                .is_none_or(|regex| tags.iter().any(|tag| regex.is_match(tag)))
+    }
+
+    pub fn render_summary(&self, tags: &[String]) -> Option<String> {
+        let template = self.summary.as_ref()?;
+        Some(
+            template
+                .replace("{project}", Self::tag_value(tags, "project:").unwrap_or(""))
+                .replace(
+                    "{description}",
+                    Self::tag_value(tags, "description:").unwrap_or(""),
+                )
+                .replace("{tags}", &tags.join(", ")),
+        )
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct MappingRules {
+    rules: Vec<MappingRule>,
+}
+
+impl MappingRules {
+    pub fn default_path(data_dir: &Path) -> PathBuf {
+        data_dir.join(RULES_FILE)
+    }
+
+    /// Loads the rules file followed by the rules from the config file. A missing rules
+    /// file simply means there are no rules in it.
+    pub fn load(path: &Path, extra: &[MappingRule]) -> Result<Self, Box<dyn Error>> {
+        let mut rules: Vec<MappingRule> = Vec::new();
+        if path.exists() {
+            let content = fs::read_to_string(path)?;
+            rules = serde_json::from_str(&content)
+                .map_err(|e| format!("Could not read mapping rules {:?}: {}", path, e))?;
+        }
+        rules.extend_from_slice(extra);
+        Self::compile(rules)
+    }
+
+    fn compile(mut rules: Vec<MappingRule>) -> Result<Self, Box<dyn Error>> {
+        for (idx, rule) in rules.iter_mut().enumerate() {
+            if let Some(pattern) = &rule.conditions.regex {
+                let regex = Regex::new(pattern)
+                    .map_err(|e| format!("Invalid regex in mapping rule {}: {}", idx + 1, e))?;
+                rule.regex = Some(regex);
+            }
+        }
+        Ok(Self { rules })
+    }
+
+    /// Returns the first rule matching the tags.
+    pub fn resolve(&self, tags: &[String]) -> Option<&MappingRule> {
+        self.rules.iter().find(|rule| rule.matches(tags))
+    }
+}
@@ -0,0 +1,26 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Set while stdout carries the entries exported by --dry-run.
+static TO_STDERR: AtomicBool = AtomicBool::new(false);
+
+/// Sends status messages to stderr from now on, so the export on stdout can be piped
+/// into a file.
+pub fn use_stderr() {
+    TO_STDERR.store(true, Ordering::Relaxed);
+}
+
+pub fn to_stderr() -> bool {
+    TO_STDERR.load(Ordering::Relaxed)
+}
+
+/// Prints a message about what the tool is doing, to stdout unless an export is written
+/// there.
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if $crate::status::to_stderr() {
+            eprintln!($($arg)*)
+        } else {
+            println!($($arg)*)
+        }
+    };
+}
@@ -0,0 +1,258 @@
+use chrono::{DateTime, NaiveDateTime, Utc};
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::TimeEntry;
+
+/// Prefix of the tags tw-upload adds to intervals after submitting them.
+pub const TAG_PREFIX: &str = "celoxis:";
+pub const SUBMITTED_TAG: &str = "celoxis:submitted";
+
+const TIMEW_DATE_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+/// How deep `import` lines in timewarrior.cfg are followed.
+const MAX_IMPORT_DEPTH: usize = 8;
+
+/// What happens to an interval that is still being tracked.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OpenIntervals {
+    /// Refuse to submit it
+    #[default]
+    Block,
+    /// Leave it out
+    Exclude,
+    /// Count it up to now, time tracked later on it is not submitted
+    Cap,
+    /// Run `timew stop` first
+    Stop,
+}
+
+/// Which tag is written back into Timewarrior for a submitted interval.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum TagMode {
+    /// Add `celoxis:submitted`
+    Submitted,
+    /// Add `celoxis:<entryId>`, falling back to `celoxis:submitted` when the id is unknown
+    Id,
+    /// Leave the Timewarrior data untouched
+    Off,
+}
+
+impl TagMode {
+    fn tag_for(&self, celoxis_id: Option<&str>) -> Option<String> {
+        match (self, celoxis_id) {
+            (TagMode::Off, _) => None,
+            (TagMode::Id, Some(id)) => Some(format!("{}{}", TAG_PREFIX, id)),
+            _ => Some(SUBMITTED_TAG.to_string()),
+        }
+    }
+}
+
+/// Reads the settings from a `timewarrior.cfg`. Indented sections are flattened, so
+///
+/// ```text
+/// reports:
+///   celoxis:
+///     time_code = engineering_labor
+/// ```
+///
+/// gives `reports.celoxis.time_code`. Imported files are read in place, later values win.
+pub fn read_config(path: &Path) -> Result<HashMap<String, String>, Box<dyn Error>> {
+    let mut settings = HashMap::new();
+    read_config_file(path, &mut settings, 0)?;
+    Ok(settings)
+}
+
+fn read_config_file(
+    path: &Path,
+    settings: &mut HashMap<String, String>,
+    depth: usize,
+) -> Result<(), Box<dyn Error>> {
+    if depth > MAX_IMPORT_DEPTH {
+        return Err(format!("Too many nested imports in {:?}", path).into());
+    }
+    let content =
+        fs::read_to_string(path).map_err(|e| format!("Could not read {:?}: {}", path, e))?;
+
+    // The sections the current line is in, with their indentation
+    let mut sections: Vec<(usize, String)> = Vec::new();
+    for line in content.lines() {
+        let line = line.split('#').next().unwrap_or_default();
+        if line.trim().is_empty() {
+            continue;
+        }
+        let indent = line.len() - line.trim_start().len();
+        let line = line.trim();
+        sections.retain(|(level, _)| *level < indent);
+
+        if let Some(file) = line.strip_prefix("import ") {
+            let file = resolve_import(path, file.trim().trim_matches('"'));
+            // Themes that aren't installed don't matter here
+            if file.exists() {
+                read_config_file(&file, settings, depth + 1)?;
+            }
+        } else if let Some((key, value)) = line.split_once('=') {
+            let mut names: Vec<&str> = sections.iter().map(|(_, name)| name.as_str()).collect();
+            names.push(key.trim());
+            let value = value.trim().trim_matches('"');
+            settings.insert(names.join("."), value.to_string());
+        } else if let Some(name) = line.strip_suffix(':') {
+            let name = name.strip_prefix("define ").unwrap_or(name).trim();
+            sections.push((indent, name.to_string()));
+        }
+    }
+    Ok(())
+}
+
+/// Imports are relative to the importing file, `~` is the home directory.
+fn resolve_import(config_path: &Path, file: &str) -> PathBuf {
+    if let (Some(rest), Some(home)) = (file.strip_prefix("~/"), dirs::home_dir()) {
+        return home.join(rest);
+    }
+    match config_path.parent() {
+        Some(dir) => dir.join(file),
+        None => PathBuf::from(file),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ExportedInterval {
+    id: u32,
+    start: String,
+    end: Option<String>,
+}
+
+fn parse_timestamp(value: &str) -> Result<DateTime<Utc>, Box<dyn Error>> {
+    let naive = NaiveDateTime::parse_from_str(value, TIMEW_DATE_FORMAT)?;
+    Ok(DateTime::<Utc>::from_naive_utc_and_offset(naive, Utc))
+}
+
+/// Runs timew on the database tw-upload reads, which need not be the one timew finds
+/// by itself.
+fn run_timew(data_dir: &Path, args: &[String]) -> Result<String, Box<dyn Error>> {
+    let output = Command::new("timew")
+        .args(args)
+        .env("TIMEWARRIORDB", data_dir)
+        .output()
+        .map_err(|e| format!("Could not run timew: {}", e))?;
+
+    if !output.status.success() {
+        return Err(format!(
+            "timew {} failed: {}",
+            args.first().map(String::as_str).unwrap_or(""),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+        .into());
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Stops tracking with `timew stop` and returns the end Timewarrior gave the interval
+/// that started at `start`.
+pub fn stop(data_dir: &Path, start: DateTime<Utc>) -> Result<DateTime<Utc>, Box<dyn Error>> {
+    run_timew(data_dir, &["stop".to_string()])?;
+
+    let exported = run_timew(
+        data_dir,
+        &[
+            "export".to_string(),
+            start.format(TIMEW_DATE_FORMAT).to_string(),
+            "-".to_string(),
+            "now".to_string(),
+        ],
+    )?;
+    let intervals: Vec<ExportedInterval> = serde_json::from_str(&exported)?;
+    for interval in intervals {
+        if parse_timestamp(&interval.start)? != start {
+            continue;
+        }
+        if let Some(end) = interval.end.as_deref() {
+            return parse_timestamp(end);
+        }
+    }
+    Err(format!(
+        "Interval starting {} is still open after `timew stop`",
+        start.format(TIMEW_DATE_FORMAT)
+    )
+    .into())
+}
+
+/// Adds the submission tag to each entry by driving `timew tag @id`.
+///
+/// The `@id`s are looked up with `timew export` over the span of the entries, matching
+/// intervals on their start and end time.
+pub fn tag_submitted(
+    data_dir: &Path,
+    entries: &[(&TimeEntry, Option<&str>)],
+    mode: TagMode,
+) -> Result<(), Box<dyn Error>> {
+    if mode == TagMode::Off || entries.is_empty() {
+        return Ok(());
+    }
+
+    let first = entries.iter().map(|(e, _)| e.start).min().unwrap();
+    let last = entries
+        .iter()
+        .map(|(e, _)| e.end.unwrap_or_else(Utc::now))
+        .max()
+        .unwrap();
+
+    let exported = run_timew(
+        data_dir,
+        &[
+            "export".to_string(),
+            first.format(TIMEW_DATE_FORMAT).to_string(),
+            "-".to_string(),
+            last.format(TIMEW_DATE_FORMAT).to_string(),
+        ],
+    )?;
+    let intervals: Vec<ExportedInterval> = serde_json::from_str(&exported)?;
+
+    let mut ids_by_tag: BTreeMap<String, Vec<u32>> = BTreeMap::new();
+    for (entry, celoxis_id) in entries {
+        let Some(tag) = mode.tag_for(*celoxis_id) else {
+            continue;
+        };
+
+        let mut found = None;
+        for interval in &intervals {
+            let start = parse_timestamp(&interval.start)?;
+            let end = interval.end.as_deref().map(parse_timestamp).transpose()?;
+            if start == entry.start && end == entry.tracked_end() {
+                found = Some(interval.id);
+                break;
+            }
+        }
+
+        match found {
+            // The parts of an interval crossing midnight share one Timewarrior interval
+            Some(id) => {
+                let ids = ids_by_tag.entry(tag).or_default();
+                if !ids.contains(&id) {
+                    ids.push(id);
+                }
+            }
+            None => status!(
+                "Warning: interval starting {} not found in timew export, not tagged",
+                entry.start.format(TIMEW_DATE_FORMAT)
+            ),
+        }
+    }
+
+    for (tag, ids) in ids_by_tag {
+        let mut args = vec!["tag".to_string()];
+        args.extend(ids.iter().map(|id| format!("@{}", id)));
+        args.push(tag.clone());
+        run_timew(data_dir, &args)?;
+        status!("Tagged {} intervals with {}", ids.len(), tag);
+    }
+
+    Ok(())
+}
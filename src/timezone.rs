@@ -0,0 +1,214 @@
+use chrono::{DateTime, Duration, Local, NaiveDate, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+
+/// The timezone whose midnight separates the days time is booked on. This is the
+/// system's local time unless `timezone` is set in the config.
+#[derive(Debug, Clone, Copy)]
+pub enum DayZone {
+    Local,
+    Named(Tz),
+}
+
+impl DayZone {
+    /// The configured timezone, or the system's without one.
+    pub fn new(timezone: Option<Tz>) -> Self {
+        match timezone {
+            Some(tz) => DayZone::Named(tz),
+            None => DayZone::Local,
+        }
+    }
+
+    pub fn date_of(&self, time: DateTime<Utc>) -> NaiveDate {
+        match self {
+            DayZone::Local => time.with_timezone(&Local).date_naive(),
+            DayZone::Named(tz) => time.with_timezone(tz).date_naive(),
+        }
+    }
+
+    /// When the given day begins. If a DST change skips midnight the day begins at the
+    /// first local time that exists, if midnight happens twice at the first of them.
+    pub fn start_of_day(&self, date: NaiveDate) -> DateTime<Utc> {
+        self.at(date, NaiveTime::MIN)
+    }
+
+    /// A local time on the given day, moved past a DST gap like midnight is.
+    pub fn at(&self, date: NaiveDate, time: NaiveTime) -> DateTime<Utc> {
+        match self {
+            DayZone::Local => at(&Local, date, time),
+            DayZone::Named(tz) => at(tz, date, time),
+        }
+    }
+
+    pub fn format(&self, time: DateTime<Utc>, format: &str) -> String {
+        match self {
+            DayZone::Local => time.with_timezone(&Local).format(format).to_string(),
+            DayZone::Named(tz) => time.with_timezone(tz).format(format).to_string(),
+        }
+    }
+}
+
+fn at<Z: TimeZone>(zone: &Z, date: NaiveDate, time: NaiveTime) -> DateTime<Utc> {
+    let local = date.and_time(time);
+    // DST gaps are an hour or less, step through them a quarter of an hour at a time
+    (0..=8)
+        .map(|step| local + Duration::minutes(15 * step))
+        .find_map(|time| zone.from_local_datetime(&time).earliest())
+        .map(|time| time.with_timezone(&Utc))
+        .unwrap_or_else(|| Utc.from_utc_datetime(&local))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TimeEntry;
+
+    fn utc(text: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(text)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    fn date(text: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(text, "%Y-%m-%d").unwrap()
+    }
+
+    fn hours_of(zone: &DayZone, day: &str) -> i64 {
+        let day = date(day);
+        let next_day = day.succ_opt().unwrap();
+        (zone.start_of_day(next_day) - zone.start_of_day(day)).num_hours()
+    }
+
+    fn entry(start: &str, end: Option<&str>) -> TimeEntry {
+        TimeEntry::new(
+            "test-0".to_string(),
+            utc(start),
+            end.map(utc),
+            vec!["test".to_string()],
+            None,
+        )
+    }
+
+    /// Day and length in minutes of every part of the split interval.
+    fn parts(entry: TimeEntry, zone: &DayZone) -> Vec<(NaiveDate, i64)> {
+        entry
+            .split_days(zone)
+            .iter()
+            .map(|part| (part.day, part.minutes()))
+            .collect()
+    }
+
+    #[test]
+    fn berlin_days_around_dst_changes() {
+        let zone = DayZone::new(Some(chrono_tz::Europe::Berlin));
+        assert_eq!(hours_of(&zone, "2024-03-30"), 24);
+        assert_eq!(hours_of(&zone, "2024-03-31"), 23);
+        assert_eq!(hours_of(&zone, "2024-10-27"), 25);
+        assert_eq!(
+            zone.start_of_day(date("2024-10-27")),
+            utc("2024-10-26T22:00:00Z")
+        );
+    }
+
+    #[test]
+    fn sao_paulo_midnight_skipped_by_spring_forward() {
+        // Clocks went from 00:00 straight to 01:00 on 2018-11-04
+        let zone = DayZone::new(Some(chrono_tz::America::Sao_Paulo));
+        assert_eq!(
+            zone.start_of_day(date("2018-11-04")),
+            utc("2018-11-04T03:00:00Z")
+        );
+        assert_eq!(hours_of(&zone, "2018-11-03"), 24);
+        assert_eq!(hours_of(&zone, "2018-11-04"), 23);
+    }
+
+    #[test]
+    fn sao_paulo_day_before_fall_back_has_25_hours() {
+        // Clocks went back from 00:00 on 2019-02-17 to 23:00 the day before
+        let zone = DayZone::new(Some(chrono_tz::America::Sao_Paulo));
+        assert_eq!(hours_of(&zone, "2019-02-16"), 25);
+        assert_eq!(
+            zone.start_of_day(date("2019-02-17")),
+            utc("2019-02-17T03:00:00Z")
+        );
+    }
+
+    #[test]
+    fn interval_within_a_day_is_not_split() {
+        let zone = DayZone::new(Some(chrono_tz::Europe::Berlin));
+        let parts = entry("2024-05-02T08:00:00Z", Some("2024-05-02T10:30:00Z")).split_days(&zone);
+        assert_eq!(parts.len(), 1);
+        assert_eq!(parts[0].day, date("2024-05-02"));
+        assert!(parts[0].part.is_none());
+    }
+
+    #[test]
+    fn interval_is_split_at_local_midnight() {
+        // 23:00 to 01:00 in Berlin summer time
+        let zone = DayZone::new(Some(chrono_tz::Europe::Berlin));
+        let entry = entry("2024-05-02T21:00:00Z", Some("2024-05-02T23:00:00Z"));
+        assert_eq!(
+            parts(entry.clone(), &zone),
+            vec![(date("2024-05-02"), 60), (date("2024-05-03"), 60)]
+        );
+
+        // The same interval lies within one day in UTC
+        let parts = entry.split_days(&DayZone::new(Some(chrono_tz::UTC)));
+        assert_eq!(parts.len(), 1);
+        assert_eq!(parts[0].day, date("2024-05-02"));
+    }
+
+    #[test]
+    fn interval_over_several_days_keeps_its_days() {
+        let zone = DayZone::new(Some(chrono_tz::UTC));
+        let split = entry("2024-05-01T20:00:00Z", Some("2024-05-03T02:00:00Z")).split_days(&zone);
+        assert_eq!(split.len(), 3);
+        assert_eq!(split[1].minutes(), 24 * 60);
+        for part in &split {
+            let days = &part.part.as_ref().unwrap().days;
+            assert_eq!(days.len(), 3);
+        }
+    }
+
+    #[test]
+    fn night_of_spring_forward_is_split_at_midnight() {
+        // 22:00 CET to 05:00 CEST, six hours because 02:00 to 03:00 doesn't exist
+        let zone = DayZone::new(Some(chrono_tz::Europe::Berlin));
+        let entry = entry("2024-03-30T21:00:00Z", Some("2024-03-31T03:00:00Z"));
+        assert_eq!(
+            parts(entry, &zone),
+            vec![(date("2024-03-30"), 2 * 60), (date("2024-03-31"), 4 * 60)]
+        );
+    }
+
+    #[test]
+    fn night_of_fall_back_is_split_at_midnight() {
+        // 22:00 CEST to 05:00 CET, eight hours because 02:00 to 03:00 happens twice
+        let zone = DayZone::new(Some(chrono_tz::Europe::Berlin));
+        let entry = entry("2024-10-26T20:00:00Z", Some("2024-10-27T04:00:00Z"));
+        assert_eq!(
+            parts(entry, &zone),
+            vec![(date("2024-10-26"), 2 * 60), (date("2024-10-27"), 6 * 60)]
+        );
+    }
+
+    #[test]
+    fn night_of_skipped_midnight_is_split_when_the_day_begins() {
+        // 23:00 -03 to 02:00 -02 in Sao Paulo, the new day began at 01:00
+        let zone = DayZone::new(Some(chrono_tz::America::Sao_Paulo));
+        let entry = entry("2018-11-04T02:00:00Z", Some("2018-11-04T04:00:00Z"));
+        assert_eq!(
+            parts(entry, &zone),
+            vec![(date("2018-11-03"), 60), (date("2018-11-04"), 60)]
+        );
+    }
+
+    #[test]
+    fn last_part_of_open_interval_stays_open() {
+        let zone = DayZone::new(Some(chrono_tz::UTC));
+        let split = entry("2024-05-01T22:00:00Z", None).split_days(&zone);
+        let last = split.last().unwrap();
+        assert!(last.open);
+        assert_eq!(last.span().1, None);
+        assert_eq!(split[0].span().1, Some(utc("2024-05-02T00:00:00Z")));
+    }
+}
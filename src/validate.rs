@@ -0,0 +1,193 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use std::collections::BTreeMap;
+
+use crate::config::ValidationSettings;
+use crate::timezone::DayZone;
+use crate::{IntervalPart, TimeEntry};
+
+/// What the checks over the intervals found. Overlaps are counted separately because
+/// they can be trimmed, the other findings are only warnings.
+#[derive(Debug, Default)]
+pub struct Findings {
+    pub overlaps: usize,
+    pub warnings: Vec<String>,
+}
+
+impl Findings {
+    pub fn print(&self) {
+        if self.warnings.is_empty() {
+            return;
+        }
+        status!(
+            "\nFound {} possible problems with the tracked time:",
+            self.warnings.len()
+        );
+        for warning in &self.warnings {
+            status!("  Warning: {}", warning);
+        }
+    }
+}
+
+fn span_end(entry: &TimeEntry) -> DateTime<Utc> {
+    entry.span().1.unwrap_or_else(Utc::now)
+}
+
+/// Indices of the entries ordered by the start of their time.
+fn by_start(entries: &[TimeEntry]) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..entries.len()).collect();
+    order.sort_by_key(|&idx| entries[idx].span().0);
+    order
+}
+
+fn hours_minutes(minutes: i64) -> String {
+    format!("{}h{:02}m", minutes / 60, minutes % 60)
+}
+
+/// Looks for overlapping intervals, long gaps within working hours, very long intervals
+/// and days with more hours than expected.
+pub fn check(entries: &[TimeEntry], settings: &ValidationSettings, zone: &DayZone) -> Findings {
+    let mut findings = Findings::default();
+    let describe = |entry: &TimeEntry| {
+        let (start, _) = entry.span();
+        format!(
+            "{}-{} {:?}",
+            zone.format(start, "%H:%M"),
+            zone.format(span_end(entry), "%H:%M"),
+            entry.tags
+        )
+    };
+
+    // Compare each interval with the one reaching furthest among those before it
+    let mut latest: Option<&TimeEntry> = None;
+    for idx in by_start(entries) {
+        let entry = &entries[idx];
+        let (start, _) = entry.span();
+        if let Some(previous) = latest {
+            let previous_end = span_end(previous);
+            if start < previous_end {
+                let shared = previous_end.min(span_end(entry)) - start;
+                findings.overlaps += 1;
+                findings.warnings.push(format!(
+                    "{} and {} on {} overlap by {}",
+                    describe(previous),
+                    describe(entry),
+                    entry.day,
+                    hours_minutes(shared.num_minutes())
+                ));
+            }
+            if span_end(entry) <= previous_end {
+                continue;
+            }
+        }
+        latest = Some(entry);
+    }
+
+    let mut days: BTreeMap<NaiveDate, Vec<&TimeEntry>> = BTreeMap::new();
+    for idx in by_start(entries) {
+        days.entry(entries[idx].day)
+            .or_default()
+            .push(&entries[idx]);
+    }
+
+    if settings.max_gap_minutes > 0 {
+        for (day, day_entries) in &days {
+            let work_start = zone.at(*day, settings.work_start);
+            let work_end = zone.at(*day, settings.work_end);
+            let mut reached = span_end(day_entries[0]);
+            for entry in &day_entries[1..] {
+                let (start, _) = entry.span();
+                // Only the part of the gap within working hours counts
+                let from = reached.max(work_start);
+                let to = start.min(work_end);
+                let gap = (to - from).num_minutes();
+                if gap > i64::from(settings.max_gap_minutes) {
+                    findings.warnings.push(format!(
+                        "nothing tracked on {} from {} to {} ({})",
+                        day,
+                        zone.format(from, "%H:%M"),
+                        zone.format(to, "%H:%M"),
+                        hours_minutes(gap)
+                    ));
+                }
+                reached = reached.max(span_end(entry));
+            }
+        }
+    }
+
+    if settings.max_interval_hours > 0.0 {
+        // The parts of an interval crossing midnight share its start
+        let mut seen = Vec::new();
+        for entry in entries {
+            let minutes = (entry.end.unwrap_or_else(Utc::now) - entry.start).num_minutes();
+            let too_long = minutes as f64 > settings.max_interval_hours * 60.0;
+            if too_long && !seen.contains(&entry.start) {
+                seen.push(entry.start);
+                findings.warnings.push(format!(
+                    "{:?} started {} is {} long",
+                    entry.tags,
+                    zone.format(entry.start, "%Y-%m-%d %H:%M"),
+                    hours_minutes(minutes)
+                ));
+            }
+        }
+    }
+
+    if settings.max_day_hours > 0.0 {
+        for (day, day_entries) in &days {
+            let minutes: i64 = day_entries.iter().map(|entry| entry.minutes()).sum();
+            if minutes as f64 > settings.max_day_hours * 60.0 {
+                findings.warnings.push(format!(
+                    "{} has {} tracked, more than {} hours",
+                    day,
+                    hours_minutes(minutes),
+                    settings.max_day_hours
+                ));
+            }
+        }
+    }
+
+    findings
+}
+
+/// Moves the start of every overlapping interval to the end of the one before it, and
+/// drops intervals that lie entirely within another. Only the time submitted changes,
+/// the Timewarrior data is left alone. Intervals that were already submitted are kept
+/// as they are. Returns how many intervals were trimmed and dropped.
+pub fn trim_overlaps(entries: &mut Vec<TimeEntry>) -> (usize, usize) {
+    let mut trimmed = 0;
+    let mut dropped = Vec::new();
+    let mut reached: Option<DateTime<Utc>> = None;
+
+    for idx in by_start(entries) {
+        let entry = &mut entries[idx];
+        let end = span_end(entry);
+        match reached {
+            Some(previous_end) if entry.span().0 < previous_end && !entry.submitted => {
+                if end <= previous_end {
+                    dropped.push(idx);
+                    continue;
+                }
+                match &mut entry.part {
+                    Some(part) => part.start = previous_end,
+                    None => {
+                        entry.part = Some(IntervalPart {
+                            start: previous_end,
+                            end: entry.end,
+                            days: vec![entry.day],
+                        })
+                    }
+                }
+                trimmed += 1;
+            }
+            _ => {}
+        }
+        reached = Some(reached.map_or(end, |previous_end| previous_end.max(end)));
+    }
+
+    // Remove from the back so the remaining indices stay valid
+    dropped.sort_unstable_by(|a, b| b.cmp(a));
+    for idx in &dropped {
+        entries.remove(*idx);
+    }
+    (trimmed, dropped.len())
+}